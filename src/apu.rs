@@ -0,0 +1,286 @@
+use crate::bus::Device;
+use crate::nes::Region;
+
+//长度计数器的装载值，由$4003/$4007/$400B/$400F的高5位索引
+const LENGTHTABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+//$4000-$4013：两个方波、三角波、噪声、DMC各4个寄存器
+const APUREGISTERSTART: u16 = 0x4000;
+const APUREGISTERCOUNT: usize = 0x14;
+//有长度计数器的通道：方波1、方波2、三角波、噪声
+const LENGTHCHANNELS: usize = 4;
+//$4011是DMC的输出电平
+const DMCOUTPUTREGISTER: usize = 0x11;
+//$4015的第4位是DMC，低4位是有长度计数器的通道
+const STATUSCHANNELS: u8 = 0b0001_1111;
+const STATUSFRAMEIRQ: u8 = 0b0100_0000;
+const STATUSDMCIRQ: u8 = 0b1000_0000;
+//$4017第7位选择5步模式，第6位禁止帧中断
+const FRAMEMODE5STEP: u8 = 0b1000_0000;
+const FRAMEIRQINHIBIT: u8 = 0b0100_0000;
+//帧计数器每一步所在的CPU周期，4步模式在第4步产生中断，5步模式的第4步什么都不做
+const NTSCFRAMESTEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PALFRAMESTEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+//各通道长度计数器暂停位所在的寄存器和位
+const LENGTHHALT: [(usize, u8); LENGTHCHANNELS] =
+    [(0x00, 0x20), (0x04, 0x20), (0x08, 0x80), (0x0C, 0x20)];
+const APUSTATUS: u16 = 0x4015;
+//$4015的第5位没有驱动，读到的是数据总线上残留的值
+const APUSTATUSOPENBUS: u8 = 0b0010_0000;
+
+//2A03的APU寄存器接口，声音合成还没有实现
+pub struct Apu {
+    registers: [u8; APUREGISTERCOUNT],     //$4000-$4013
+    enabled: u8,                           //$4015写入的通道使能
+    length_counters: [u8; LENGTHCHANNELS], //为0时通道静音
+    frame_irq: bool,                       //帧计数器中断标志
+    dmc_irq: bool,                         //DMC中断标志
+    frame_counter: u8,                     //$4017写入的模式
+    frame_cycle: u32,                      //帧计数器在当前序列中经过的CPU周期
+    region: Region,                        //决定帧计数器的步长
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            registers: [0; APUREGISTERCOUNT],
+            enabled: 0,
+            length_counters: [0; LENGTHCHANNELS],
+            frame_irq: false,
+            dmc_irq: false,
+            frame_counter: 0,
+            frame_cycle: 0,
+            region: Region::default(),
+        }
+    }
+
+    //上电：$4000-$4013、$4015、$4017全部为0
+    pub fn power_on(&mut self) {
+        let region = self.region;
+        *self = Self::new();
+        self.region = region;
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    //复位：相当于写$4015=0让所有通道静音，DMC输出电平只保留最低位
+    //$4017的模式保持不变
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0);
+        self.frame_irq = false;
+        self.frame_cycle = 0;
+        self.registers[DMCOUTPUTREGISTER] &= 1;
+    }
+
+    //每个CPU周期调用一次，推进帧计数器
+    pub fn tick(&mut self) {
+        let steps = match self.region {
+            Region::Ntsc => &NTSCFRAMESTEPS,
+            Region::Pal => &PALFRAMESTEPS,
+        };
+        let five_step = self.frame_counter & FRAMEMODE5STEP != 0;
+        let last = if five_step { 4 } else { 3 };
+        self.frame_cycle += 1;
+        //半帧时长度计数器减一
+        if self.frame_cycle == steps[1] || self.frame_cycle == steps[last] {
+            self.clock_length_counters();
+        }
+        if !five_step && self.frame_cycle == steps[3] && self.frame_counter & FRAMEIRQINHIBIT == 0 {
+            self.frame_irq = true;
+        }
+        if self.frame_cycle > steps[last] {
+            self.frame_cycle = 0;
+        }
+    }
+
+    fn clock_length_counters(&mut self) {
+        for (channel, (register, halt)) in LENGTHHALT.iter().enumerate() {
+            if self.registers[*register] & halt == 0 && self.length_counters[channel] > 0 {
+                self.length_counters[channel] -= 1;
+            }
+        }
+    }
+
+    //IRQ输出线
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc_irq
+    }
+
+    //读$4015会清除帧中断标志
+    pub fn read_status(&mut self) -> u8 {
+        let value = self.peek_status();
+        self.frame_irq = false;
+        value
+    }
+
+    pub fn peek_status(&self) -> u8 {
+        let mut value = 0;
+        for (channel, length) in self.length_counters.iter().enumerate() {
+            if *length > 0 {
+                value |= 1 << channel;
+            }
+        }
+        if self.frame_irq {
+            value |= STATUSFRAMEIRQ;
+        }
+        if self.dmc_irq {
+            value |= STATUSDMCIRQ;
+        }
+        value
+    }
+
+    //add是$4000-$4013、$4015或$4017
+    pub fn write_register(&mut self, add: u16, value: u8) {
+        match add {
+            0x4000..=0x4013 => {
+                let index = (add - APUREGISTERSTART) as usize;
+                self.registers[index] = value;
+                //每个通道的第4个寄存器装载长度计数器，通道被禁止时不装载
+                let channel = index / 4;
+                if index % 4 == 3 && channel < LENGTHCHANNELS && self.enabled & (1 << channel) != 0
+                {
+                    self.length_counters[channel] = LENGTHTABLE[value as usize >> 3];
+                }
+            }
+            //禁止通道会立即清零它的长度计数器，写$4015还会清除DMC中断
+            0x4015 => {
+                self.enabled = value & STATUSCHANNELS;
+                for (channel, length) in self.length_counters.iter_mut().enumerate() {
+                    if self.enabled & (1 << channel) == 0 {
+                        *length = 0;
+                    }
+                }
+                self.dmc_irq = false;
+            }
+            //写$4017重新开始帧序列，5步模式立即产生一次半帧时钟
+            0x4017 => {
+                self.frame_counter = value;
+                self.frame_cycle = 0;
+                if value & FRAMEIRQINHIBIT != 0 {
+                    self.frame_irq = false;
+                }
+                if value & FRAMEMODE5STEP != 0 {
+                    self.clock_length_counters();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+//只有$4015可读，$4014和$4016不属于APU
+impl Device for Apu {
+    fn read(&mut self, add: u16, open_bus: u8) -> Option<u8> {
+        (add == APUSTATUS).then(|| self.read_status() | (open_bus & APUSTATUSOPENBUS))
+    }
+
+    fn write(&mut self, add: u16, value: u8) -> bool {
+        let handled = matches!(add, 0x4000..=0x4013 | APUSTATUS | 0x4017);
+        if handled {
+            self.write_register(add, value);
+        }
+        handled
+    }
+
+    fn peek(&self, add: u16, open_bus: u8) -> Option<u8> {
+        (add == APUSTATUS).then(|| self.peek_status() | (open_bus & APUSTATUSOPENBUS))
+    }
+
+    fn tick(&mut self) {
+        Apu::tick(self);
+    }
+
+    fn irq(&self) -> bool {
+        Apu::irq(self)
+    }
+
+    fn power_on(&mut self) {
+        Apu::power_on(self);
+    }
+
+    fn reset(&mut self) {
+        Apu::reset(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_counter_should_load_only_when_enabled() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4003, 0b0000_1000);
+        assert_eq!(apu.peek_status(), 0);
+        apu.write_register(0x4015, 0b0000_0101);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x400b, 0b0000_1000);
+        assert_eq!(apu.length_counters[0], 254);
+        assert_eq!(apu.peek_status(), 0b0000_0101);
+        apu.write_register(0x4015, 0b0000_0001);
+        assert_eq!(apu.peek_status(), 0b0000_0001);
+    }
+
+    #[test]
+    fn frame_counter_should_raise_irq_in_four_step_mode() {
+        let mut apu = Apu::new();
+        for _ in 0..NTSCFRAMESTEPS[3] - 1 {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+        apu.tick();
+        assert!(apu.irq());
+        assert_eq!(apu.read_status() & STATUSFRAMEIRQ, STATUSFRAMEIRQ);
+        assert!(!apu.irq());
+        //5步模式和禁止中断时都不产生中断
+        apu.write_register(0x4017, FRAMEMODE5STEP);
+        for _ in 0..NTSCFRAMESTEPS[4] * 2 {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+        apu.write_register(0x4017, FRAMEIRQINHIBIT);
+        for _ in 0..NTSCFRAMESTEPS[3] * 2 {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn half_frames_should_clock_length_counters() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_0011);
+        apu.write_register(0x4003, 0b0001_1000); //长度2
+        apu.write_register(0x4004, 0x20);
+        apu.write_register(0x4007, 0b0001_1000);
+        for _ in 0..NTSCFRAMESTEPS[3] {
+            apu.tick();
+        }
+        assert_eq!(apu.length_counters, [0, 2, 0, 0]);
+        assert_eq!(apu.peek_status() & STATUSCHANNELS, 0b0000_0010);
+    }
+
+    #[test]
+    fn reset_should_silence_channels_and_keep_dmc_low_bit() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x4011, 0b0101_0101);
+        apu.write_register(0x4000, 0x3f);
+        apu.reset();
+        assert_eq!(apu.peek_status(), 0);
+        assert_eq!(apu.registers[DMCOUTPUTREGISTER], 0b0000_0001);
+        assert_eq!(apu.registers[0], 0x3f);
+        apu.power_on();
+        assert_eq!(apu.registers, [0; APUREGISTERCOUNT]);
+    }
+}
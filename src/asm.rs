@@ -0,0 +1,951 @@
+use crate::cpuoperand::AddressingModes;
+use crate::cpuoperand::OpCode;
+use crate::cpuoperand::CPU_OPRAND;
+use crate::memory::Memory;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fmt;
+
+//语法：
+//  label:  LDA #<value+1   ;注释
+//  value = $10 * 2
+//  .org $C000 / .byte 1, "abc" / .word label
+//数字可以写成$FF、%1010、255或'a'，*表示当前地址
+//运算符：+ - * / & | ^ << >>，一元 - ~ <（低字节） >（高字节）
+//超过两位的十六进制数（如$0010）按16位地址处理
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    UnknownMnemonic {
+        line: usize,
+        mnemonic: String,
+    },
+    UnknownDirective {
+        line: usize,
+        directive: String,
+    },
+    //操作数的写法无法识别
+    InvalidOperand {
+        line: usize,
+        operand: String,
+    },
+    InvalidExpression {
+        line: usize,
+        expression: String,
+    },
+    //指令没有这种寻址方式
+    UnsupportedMode {
+        line: usize,
+        mnemonic: String,
+        mode: AddressingModes,
+    },
+    UndefinedSymbol {
+        line: usize,
+        name: String,
+    },
+    DuplicateSymbol {
+        line: usize,
+        name: String,
+    },
+    BranchOutOfRange {
+        line: usize,
+        target: u16,
+    },
+    ValueOutOfRange {
+        line: usize,
+        value: i64,
+    },
+    //.org不能回到已经输出过的地址
+    OrgBackwards {
+        line: usize,
+        address: u16,
+    },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "line {}: unknown mnemonic {}", line, mnemonic)
+            }
+            AsmError::UnknownDirective { line, directive } => {
+                write!(f, "line {}: unknown directive {}", line, directive)
+            }
+            AsmError::InvalidOperand { line, operand } => {
+                write!(f, "line {}: invalid operand {}", line, operand)
+            }
+            AsmError::InvalidExpression { line, expression } => {
+                write!(f, "line {}: invalid expression {}", line, expression)
+            }
+            AsmError::UnsupportedMode {
+                line,
+                mnemonic,
+                mode,
+            } => write!(f, "line {}: {} does not support {:?}", line, mnemonic, mode),
+            AsmError::UndefinedSymbol { line, name } => {
+                write!(f, "line {}: undefined symbol {}", line, name)
+            }
+            AsmError::DuplicateSymbol { line, name } => {
+                write!(f, "line {}: symbol {} is already defined", line, name)
+            }
+            AsmError::BranchOutOfRange { line, target } => {
+                write!(
+                    f,
+                    "line {}: branch target {:04X} is out of range",
+                    line, target
+                )
+            }
+            AsmError::ValueOutOfRange { line, value } => {
+                write!(f, "line {}: value {} is out of range", line, value)
+            }
+            AsmError::OrgBackwards { line, address } => {
+                write!(
+                    f,
+                    "line {}: .org {:04X} is behind the current address",
+                    line, address
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+lazy_static! {
+    //(助记符, 寻址方式) -> 操作码，同一组合有多个操作码时优先官方指令，其次取表中第一个
+    static ref ASSEMBLY_TABLE: HashMap<(&'static str, AddressingModes), &'static OpCode> = {
+        let mut table: HashMap<(&'static str, AddressingModes), &'static OpCode> = HashMap::new();
+        for opcode in CPU_OPRAND.iter() {
+            let key = (opcode.opname, opcode.addressmode);
+            match table.get(&key) {
+                Some(existing) if !existing.unofficial || opcode.unofficial => {}
+                _ => {
+                    table.insert(key, opcode);
+                }
+            }
+        }
+        table
+    };
+}
+
+fn supports(mnemonic: &str, mode: AddressingModes) -> bool {
+    ASSEMBLY_TABLE.contains_key(&(mnemonic, mode))
+}
+
+fn is_mnemonic(mnemonic: &str) -> bool {
+    CPU_OPRAND.iter().any(|opcode| opcode.opname == mnemonic)
+}
+
+//汇编源代码，返回从origin开始的连续字节，.org跳过的部分填0
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, AsmError> {
+    Assembler::new(source, origin)?.run()
+}
+
+//把汇编结果直接写到总线上，返回写入的字节数，供调试器修改代码
+pub fn patch<M: Memory>(memory: &mut M, address: u16, source: &str) -> Result<usize, AsmError> {
+    let bytes = assemble(source, address)?;
+    for (offset, value) in bytes.iter().enumerate() {
+        memory.write(address.wrapping_add(offset as u16), *value);
+    }
+    Ok(bytes.len())
+}
+
+//操作数的写法，表达式还没有求值
+#[derive(Debug, Clone, Copy)]
+enum Operand<'a> {
+    None,
+    Accumulator,
+    Immediate(&'a str),
+    Indirect(&'a str),
+    IndexedIndirect(&'a str),
+    IndirectIndexed(&'a str),
+    Direct(&'a str),
+    DirectX(&'a str),
+    DirectY(&'a str),
+}
+
+#[derive(Debug, Clone)]
+enum Statement<'a> {
+    Empty,
+    Instruction {
+        mnemonic: String,
+        operand: Operand<'a>,
+    },
+    Byte(Vec<&'a str>),
+    Word(Vec<&'a str>),
+    Org(&'a str),
+    Assign(&'a str, &'a str),
+}
+
+struct Line<'a> {
+    number: usize,
+    label: Option<&'a str>,
+    statement: Statement<'a>,
+}
+
+struct Assembler<'a> {
+    lines: Vec<Line<'a>>,
+    origin: u16,
+    symbols: HashMap<&'a str, i64>,
+    //第一遍为每条指令选定的寻址方式，第二遍沿用，保证两遍的长度一致
+    modes: Vec<Option<AddressingModes>>,
+}
+
+//去掉分号后面的注释，引号里的分号不算
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &text[..index],
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    text
+}
+
+//按逗号分割，引号里的逗号不算
+fn split_arguments(text: &str) -> Vec<&str> {
+    let mut arguments = vec![];
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ',') => {
+                arguments.push(text[start..index].trim());
+                start = index + 1;
+            }
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    arguments.push(text[start..].trim());
+    arguments
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_operand(text: &str) -> Option<Operand<'_>> {
+    let upper = text.to_ascii_uppercase();
+    if text.is_empty() {
+        return Some(Operand::None);
+    }
+    if upper == "A" {
+        return Some(Operand::Accumulator);
+    }
+    if let Some(expression) = text.strip_prefix('#') {
+        return Some(Operand::Immediate(expression.trim()));
+    }
+    let compact: String = upper.chars().filter(|c| !c.is_whitespace()).collect();
+    if text.starts_with('(') {
+        if compact.ends_with(",X)") {
+            let end = text.rfind(',')?;
+            return Some(Operand::IndexedIndirect(text[1..end].trim()));
+        }
+        if compact.ends_with("),Y") {
+            let end = text.rfind(')')?;
+            return Some(Operand::IndirectIndexed(text[1..end].trim()));
+        }
+        if text.ends_with(')') {
+            return Some(Operand::Indirect(text[1..text.len() - 1].trim()));
+        }
+    }
+    if compact.ends_with(",X") {
+        return Some(Operand::DirectX(text[..text.rfind(',')?].trim()));
+    }
+    if compact.ends_with(",Y") {
+        return Some(Operand::DirectY(text[..text.rfind(',')?].trim()));
+    }
+    Some(Operand::Direct(text))
+}
+
+fn parse_line(number: usize, text: &str) -> Result<Line<'_>, AsmError> {
+    let mut text = strip_comment(text).trim();
+    let mut label = None;
+    if let Some((name, rest)) = text.split_once(':') {
+        if is_identifier(name.trim()) {
+            label = Some(name.trim());
+            text = rest.trim();
+        }
+    }
+    if let Some((name, expression)) = text.split_once('=') {
+        if is_identifier(name.trim()) {
+            return Ok(Line {
+                number,
+                label,
+                statement: Statement::Assign(name.trim(), expression.trim()),
+            });
+        }
+    }
+
+    let (word, rest) = match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    };
+    let statement = if word.is_empty() {
+        Statement::Empty
+    } else if word.starts_with('.') {
+        match word.to_ascii_lowercase().as_str() {
+            ".byte" => Statement::Byte(split_arguments(rest)),
+            ".word" => Statement::Word(split_arguments(rest)),
+            ".org" => Statement::Org(rest),
+            _ => {
+                return Err(AsmError::UnknownDirective {
+                    line: number,
+                    directive: word.to_string(),
+                })
+            }
+        }
+    } else {
+        let mnemonic = word.to_ascii_uppercase();
+        if !is_mnemonic(&mnemonic) {
+            return Err(AsmError::UnknownMnemonic {
+                line: number,
+                mnemonic: word.to_string(),
+            });
+        }
+        let operand = parse_operand(rest).ok_or_else(|| AsmError::InvalidOperand {
+            line: number,
+            operand: rest.to_string(),
+        })?;
+        Statement::Instruction { mnemonic, operand }
+    };
+    Ok(Line {
+        number,
+        label,
+        statement,
+    })
+}
+
+//超过两位的十六进制字面量强制16位，例如$0010
+fn forces_absolute(expression: &str) -> bool {
+    matches!(expression.strip_prefix('$'), Some(digits) if digits.len() > 2)
+}
+
+//根据写法和值选择寻址方式，值未知（前向引用）时按16位处理
+fn select_mode(
+    mnemonic: &str,
+    operand: Operand,
+    value: Option<i64>,
+    line: usize,
+) -> Result<AddressingModes, AsmError> {
+    let zero_page = |expression: &str| {
+        !forces_absolute(expression) && matches!(value, Some(value) if (0..=0xff).contains(&value))
+    };
+    let mode = match operand {
+        Operand::None if !supports(mnemonic, AddressingModes::NoAddressingMode) => {
+            AddressingModes::Accumulator
+        }
+        Operand::None => AddressingModes::NoAddressingMode,
+        Operand::Accumulator => AddressingModes::Accumulator,
+        Operand::Immediate(_) => AddressingModes::Immediate,
+        Operand::Indirect(_) => AddressingModes::Indirect,
+        Operand::IndexedIndirect(_) => AddressingModes::IndexedIndirect,
+        Operand::IndirectIndexed(_) => AddressingModes::IndirectIndexed,
+        Operand::Direct(_) if supports(mnemonic, AddressingModes::Relative) => {
+            AddressingModes::Relative
+        }
+        Operand::Direct(expression)
+            if zero_page(expression) && supports(mnemonic, AddressingModes::ZeroPage) =>
+        {
+            AddressingModes::ZeroPage
+        }
+        Operand::Direct(_) => AddressingModes::Absolute,
+        Operand::DirectX(expression)
+            if zero_page(expression) && supports(mnemonic, AddressingModes::ZeroPageX) =>
+        {
+            AddressingModes::ZeroPageX
+        }
+        Operand::DirectX(_) => AddressingModes::AbsoluteX,
+        Operand::DirectY(expression)
+            if zero_page(expression) && supports(mnemonic, AddressingModes::ZeroPageY) =>
+        {
+            AddressingModes::ZeroPageY
+        }
+        Operand::DirectY(_) => AddressingModes::AbsoluteY,
+    };
+    if !supports(mnemonic, mode) {
+        return Err(AsmError::UnsupportedMode {
+            line,
+            mnemonic: mnemonic.to_string(),
+            mode,
+        });
+    }
+    Ok(mode)
+}
+
+fn operand_expression(operand: Operand<'_>) -> Option<&str> {
+    match operand {
+        Operand::None | Operand::Accumulator => None,
+        Operand::Immediate(expression)
+        | Operand::Indirect(expression)
+        | Operand::IndexedIndirect(expression)
+        | Operand::IndirectIndexed(expression)
+        | Operand::Direct(expression)
+        | Operand::DirectX(expression)
+        | Operand::DirectY(expression) => Some(expression),
+    }
+}
+
+//字符串按字节输出，其他参数是一个字节的表达式
+fn string_literal(argument: &str) -> Option<&str> {
+    argument
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+}
+
+fn check_range(value: i64, min: i64, max: i64, line: usize) -> Result<i64, AsmError> {
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(AsmError::ValueOutOfRange { line, value })
+    }
+}
+
+impl<'a> Assembler<'a> {
+    fn new(source: &'a str, origin: u16) -> Result<Self, AsmError> {
+        let lines = source
+            .lines()
+            .enumerate()
+            .map(|(index, text)| parse_line(index + 1, text))
+            .collect::<Result<Vec<_>, _>>()?;
+        let modes = vec![None; lines.len()];
+        Ok(Assembler {
+            lines,
+            origin,
+            symbols: HashMap::new(),
+            modes,
+        })
+    }
+
+    fn run(mut self) -> Result<Vec<u8>, AsmError> {
+        self.first_pass()?;
+        self.second_pass()
+    }
+
+    fn define(&mut self, name: &'a str, value: i64, line: usize) -> Result<(), AsmError> {
+        if self.symbols.insert(name, value).is_some() {
+            return Err(AsmError::DuplicateSymbol {
+                line,
+                name: name.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn evaluate(&self, expression: &str, pc: u16, line: usize) -> Result<i64, AsmError> {
+        evaluate(expression, &self.symbols, pc).map_err(|error| match error {
+            EvalError::Undefined(name) => AsmError::UndefinedSymbol { line, name },
+            EvalError::Invalid => AsmError::InvalidExpression {
+                line,
+                expression: expression.to_string(),
+            },
+        })
+    }
+
+    //第一遍：确定每条指令的长度和所有标签的地址
+    fn first_pass(&mut self) -> Result<(), AsmError> {
+        let mut pc = self.origin;
+        for index in 0..self.lines.len() {
+            let number = self.lines[index].number;
+            if let Some(label) = self.lines[index].label {
+                self.define(label, pc as i64, number)?;
+            }
+            match self.lines[index].statement.clone() {
+                Statement::Empty => {}
+                Statement::Assign(name, expression) => {
+                    let value = self.evaluate(expression, pc, number)?;
+                    self.define(name, value, number)?;
+                }
+                Statement::Instruction { mnemonic, operand } => {
+                    let value = match operand_expression(operand) {
+                        Some(expression) => match self.evaluate(expression, pc, number) {
+                            Ok(value) => Some(value),
+                            Err(AsmError::UndefinedSymbol { .. }) => None,
+                            Err(error) => return Err(error),
+                        },
+                        None => None,
+                    };
+                    let mode = select_mode(&mnemonic, operand, value, number)?;
+                    self.modes[index] = Some(mode);
+                    let bytes = ASSEMBLY_TABLE[&(mnemonic.as_str(), mode)].bytes;
+                    pc = pc.wrapping_add(bytes as u16);
+                }
+                Statement::Byte(arguments) => {
+                    for argument in arguments {
+                        let size = string_literal(argument).map_or(1, str::len);
+                        pc = pc.wrapping_add(size as u16);
+                    }
+                }
+                Statement::Word(arguments) => {
+                    pc = pc.wrapping_add(2 * arguments.len() as u16);
+                }
+                Statement::Org(expression) => {
+                    let address = self.evaluate(expression, pc, number)?;
+                    let address = check_range(address, 0, 0xffff, number)? as u16;
+                    if address < pc {
+                        return Err(AsmError::OrgBackwards {
+                            line: number,
+                            address,
+                        });
+                    }
+                    pc = address;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //第二遍：所有符号都已知，输出字节
+    fn second_pass(&self) -> Result<Vec<u8>, AsmError> {
+        let mut output = vec![];
+        for (index, line) in self.lines.iter().enumerate() {
+            let number = line.number;
+            let pc = self.origin.wrapping_add(output.len() as u16);
+            match &line.statement {
+                Statement::Empty | Statement::Assign(..) => {}
+                Statement::Instruction { mnemonic, operand } => {
+                    let mode = self.modes[index].expect("mode selected in first pass");
+                    let opcode = ASSEMBLY_TABLE[&(mnemonic.as_str(), mode)];
+                    output.push(opcode.opcode);
+                    let Some(expression) = operand_expression(*operand) else {
+                        continue;
+                    };
+                    let value = self.evaluate(expression, pc, number)?;
+                    match mode {
+                        AddressingModes::Relative => {
+                            let target = check_range(value, 0, 0xffff, number)? as u16;
+                            let offset = target as i64 - (pc as i64 + 2);
+                            if !(-128..=127).contains(&offset) {
+                                return Err(AsmError::BranchOutOfRange {
+                                    line: number,
+                                    target,
+                                });
+                            }
+                            output.push(offset as u8);
+                        }
+                        AddressingModes::Immediate => {
+                            output.push(check_range(value, -128, 0xff, number)? as u8);
+                        }
+                        _ if opcode.bytes == 2 => {
+                            output.push(check_range(value, 0, 0xff, number)? as u8);
+                        }
+                        _ => {
+                            let value = check_range(value, 0, 0xffff, number)? as u16;
+                            output.extend_from_slice(&value.to_le_bytes());
+                        }
+                    }
+                }
+                Statement::Byte(arguments) => {
+                    for argument in arguments {
+                        match string_literal(argument) {
+                            Some(text) => output.extend_from_slice(text.as_bytes()),
+                            None => {
+                                let value = self.evaluate(argument, pc, number)?;
+                                output.push(check_range(value, -128, 0xff, number)? as u8);
+                            }
+                        }
+                    }
+                }
+                Statement::Word(arguments) => {
+                    for argument in arguments {
+                        let value = self.evaluate(argument, pc, number)?;
+                        let value = check_range(value, -0x8000, 0xffff, number)? as u16;
+                        output.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+                Statement::Org(expression) => {
+                    let address = self.evaluate(expression, pc, number)? as u16;
+                    output.resize(address.wrapping_sub(self.origin) as usize, 0);
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+//表达式求值
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EvalError {
+    Undefined(String),
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 14] = [
+    "<<", ">>", "+", "-", "*", "/", "&", "|", "^", "~", "<", ">", "(", ")",
+];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, EvalError> {
+    let mut tokens = vec![];
+    let mut rest = expression.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap_or(' ');
+        let (token, length) = if let Some(digits) = rest.strip_prefix('$') {
+            let length = digits
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(digits.len());
+            let value =
+                i64::from_str_radix(&digits[..length], 16).map_err(|_| EvalError::Invalid)?;
+            (Token::Number(value), length + 1)
+        } else if let Some(digits) = rest.strip_prefix('%') {
+            let length = digits
+                .find(|c| c != '0' && c != '1')
+                .unwrap_or(digits.len());
+            let value =
+                i64::from_str_radix(&digits[..length], 2).map_err(|_| EvalError::Invalid)?;
+            (Token::Number(value), length + 1)
+        } else if c.is_ascii_digit() {
+            let length = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let value = rest[..length].parse().map_err(|_| EvalError::Invalid)?;
+            (Token::Number(value), length)
+        } else if c == '\'' {
+            let mut chars = rest[1..].chars();
+            match (chars.next(), chars.next()) {
+                (Some(value), Some('\'')) if value.is_ascii() => (Token::Number(value as i64), 3),
+                _ => return Err(EvalError::Invalid),
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let length = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (Token::Symbol(rest[..length].to_string()), length)
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or(EvalError::Invalid)?;
+            (Token::Op(op), op.len())
+        };
+        tokens.push(token);
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+fn evaluate(expression: &str, symbols: &HashMap<&str, i64>, pc: u16) -> Result<i64, EvalError> {
+    let tokens = tokenize(expression)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        position: 0,
+        symbols,
+        pc,
+    };
+    let value = parser.binary(0)?;
+    if parser.position != tokens.len() {
+        return Err(EvalError::Invalid);
+    }
+    Ok(value)
+}
+
+//二元运算符的优先级，从低到高
+const PRECEDENCE: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/"],
+];
+
+struct Parser<'t, 's> {
+    tokens: &'t [Token],
+    position: usize,
+    symbols: &'s HashMap<&'s str, i64>,
+    pc: u16,
+}
+
+impl Parser<'_, '_> {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, EvalError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| PRECEDENCE[level].contains(op)) {
+            self.position += 1;
+            let rhs = self.binary(level + 1)?;
+            value = match op {
+                "|" => value | rhs,
+                "^" => value ^ rhs,
+                "&" => value & rhs,
+                "<<" => value.checked_shl(rhs as u32).ok_or(EvalError::Invalid)?,
+                ">>" => value.checked_shr(rhs as u32).ok_or(EvalError::Invalid)?,
+                "+" => value.checked_add(rhs).ok_or(EvalError::Invalid)?,
+                "-" => value.checked_sub(rhs).ok_or(EvalError::Invalid)?,
+                "*" => value.checked_mul(rhs).ok_or(EvalError::Invalid)?,
+                _ => value.checked_div(rhs).ok_or(EvalError::Invalid)?,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, EvalError> {
+        match self.peek_op() {
+            Some("-") => {
+                self.position += 1;
+                self.unary()?.checked_neg().ok_or(EvalError::Invalid)
+            }
+            Some("~") => {
+                self.position += 1;
+                Ok(!self.unary()? & 0xffff)
+            }
+            Some("<") => {
+                self.position += 1;
+                Ok(self.unary()? & 0xff)
+            }
+            Some(">") => {
+                self.position += 1;
+                Ok((self.unary()? >> 8) & 0xff)
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, EvalError> {
+        let pc = self.pc;
+        let symbols = self.symbols;
+        match self.next().cloned() {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Symbol(name)) => symbols
+                .get(name.as_str())
+                .copied()
+                .ok_or(EvalError::Undefined(name)),
+            //*在操作数位置表示当前指令的地址
+            Some(Token::Op("*")) => Ok(pc as i64),
+            Some(Token::Op("(")) => {
+                let value = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Op(")")) => Ok(value),
+                    _ => Err(EvalError::Invalid),
+                }
+            }
+            _ => Err(EvalError::Invalid),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuoperand::CPU_OPRAND_TABLE;
+    use crate::disasm::{disassemble, DisassemblyOptions};
+    use crate::memory::FlatMemory;
+
+    #[test]
+    fn assemble_should_encode_all_addressing_modes() {
+        let source = "
+            CLC
+            ASL
+            ASL A
+            LDA #$10
+            LDA $10
+            LDA $10,X
+            LDX $10,Y
+            LDA $1234
+            LDA $1234,X
+            LDA $1234,y
+            JMP ($1234)
+            LDA ($10,X)
+            LDA ($10),Y
+            LDA $0010
+        ";
+        assert_eq!(
+            assemble(source, 0x8000).unwrap(),
+            vec![
+                0x18, 0x0a, 0x0a, 0xa9, 0x10, 0xa5, 0x10, 0xb5, 0x10, 0xb6, 0x10, 0xad, 0x34, 0x12,
+                0xbd, 0x34, 0x12, 0xb9, 0x34, 0x12, 0x6c, 0x34, 0x12, 0xa1, 0x10, 0xb1, 0x10, 0xad,
+                0x10, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn assemble_should_resolve_labels() {
+        let source = "
+        start:  LDX #3
+        loop:   DEX
+                BNE loop
+                JMP end     ; 前向引用
+                .byte 1, 2
+        end:    BRK
+        ";
+        assert_eq!(
+            assemble(source, 0x8000).unwrap(),
+            vec![0xa2, 0x03, 0xca, 0xd0, 0xfd, 0x4c, 0x0a, 0x80, 0x01, 0x02, 0x00]
+        );
+    }
+
+    #[test]
+    fn assemble_should_evaluate_expressions() {
+        let source = "
+            value = $1234
+            count = 2 * (3 + 4)
+            LDA #<value
+            LDX #>value
+            LDY #count - 1
+            LDA #%1010 | 1
+            LDA #'A'
+            LDA value + 1, X
+            JMP *
+        ";
+        assert_eq!(
+            assemble(source, 0x8000).unwrap(),
+            vec![
+                0xa9, 0x34, 0xa2, 0x12, 0xa0, 0x0d, 0xa9, 0x0b, 0xa9, 0x41, 0xbd, 0x35, 0x12, 0x4c,
+                0x0d, 0x80,
+            ]
+        );
+    }
+
+    #[test]
+    fn assemble_should_handle_directives() {
+        let source = "
+            .word end, $1234
+            .byte \"hi\", -1
+            .org $8008
+        end: NOP
+        ";
+        assert_eq!(
+            assemble(source, 0x8000).unwrap(),
+            vec![0x08, 0x80, 0x34, 0x12, b'h', b'i', 0xff, 0x00, 0xea]
+        );
+    }
+
+    #[test]
+    fn assemble_should_prefer_official_opcodes() {
+        assert_eq!(assemble("NOP\nSBC #1", 0).unwrap(), vec![0xea, 0xe9, 0x01]);
+        assert_eq!(
+            assemble("LAX $10\nNOP $10", 0).unwrap(),
+            vec![0xa7, 0x10, 0x04, 0x10]
+        );
+    }
+
+    #[test]
+    fn overflowing_expressions_should_be_invalid() {
+        let overflows = [
+            "$7FFFFFFFFFFFFFFF+1",
+            "-$7FFFFFFFFFFFFFFF-2",
+            "$7FFFFFFFFFFFFFFF*2",
+            "-(-$7FFFFFFFFFFFFFFF-1)",
+        ];
+        for expression in overflows {
+            let source = format!(".word {}", expression);
+            assert_eq!(
+                assemble(&source, 0),
+                Err(AsmError::InvalidExpression {
+                    line: 1,
+                    expression: String::from(expression)
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn assemble_should_report_errors() {
+        assert_eq!(
+            assemble("\nFOO", 0),
+            Err(AsmError::UnknownMnemonic {
+                line: 2,
+                mnemonic: String::from("FOO")
+            })
+        );
+        assert_eq!(
+            assemble("LDA missing", 0),
+            Err(AsmError::UndefinedSymbol {
+                line: 1,
+                name: String::from("missing")
+            })
+        );
+        assert_eq!(
+            assemble("STA #1", 0),
+            Err(AsmError::UnsupportedMode {
+                line: 1,
+                mnemonic: String::from("STA"),
+                mode: AddressingModes::Immediate
+            })
+        );
+        assert_eq!(
+            assemble("a: NOP\na: NOP", 0),
+            Err(AsmError::DuplicateSymbol {
+                line: 2,
+                name: String::from("a")
+            })
+        );
+        assert_eq!(
+            assemble("BNE $9000", 0x8000),
+            Err(AsmError::BranchOutOfRange {
+                line: 1,
+                target: 0x9000
+            })
+        );
+        assert_eq!(
+            assemble("LDA #256", 0),
+            Err(AsmError::ValueOutOfRange {
+                line: 1,
+                value: 256
+            })
+        );
+        assert_eq!(
+            assemble("NOP\n.org 0", 0x10),
+            Err(AsmError::OrgBackwards {
+                line: 2,
+                address: 0
+            })
+        );
+    }
+
+    #[test]
+    fn disassembly_should_assemble_back_to_same_bytes() {
+        //同一组合有多个操作码时只检查汇编器会选中的那个
+        for opcode in CPU_OPRAND_TABLE.iter().flatten() {
+            let bytes = [opcode.opcode, 0x34, 0x12];
+            let line = &disassemble(&bytes, 0x8000, &DisassemblyOptions::default())[0];
+            if ASSEMBLY_TABLE[&(opcode.opname, opcode.addressmode)].opcode != opcode.opcode {
+                continue;
+            }
+            assert_eq!(
+                assemble(&line.text, 0x8000).unwrap(),
+                line.bytes,
+                "{}",
+                line.text
+            );
+        }
+    }
+
+    #[test]
+    fn patch_should_write_to_memory() {
+        let mut memory = FlatMemory::new();
+        assert_eq!(patch(&mut memory, 0xc000, "JMP *").unwrap(), 3);
+        assert_eq!(memory.peek(0xc000), 0x4c);
+        assert_eq!(memory.peek(0xc001), 0x00);
+        assert_eq!(memory.peek(0xc002), 0xc0);
+    }
+}
@@ -0,0 +1,562 @@
+use crate::apu::Apu;
+use crate::cartridges::{Mirroring, Rom, RomError};
+use crate::joypads::{Joypad, Joypads};
+use crate::mapper::{mapper_for, Cartridge, Nrom, SharedMapper};
+use crate::memory::Memory;
+use crate::nes::Region;
+use crate::ppu::Ppu;
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+//  _______________ $10000  _______________
+// | PRG-ROM       |       |               |
+// | Upper Bank    |       |               |
+// |_ _ _ _ _ _ _ _| $C000 | PRG-ROM       |
+// | PRG-ROM       |       |               |
+// | Lower Bank    |       |               |
+// |_______________| $8000 |_______________|
+// | SRAM          |       | SRAM          |
+// |_______________| $6000 |_______________|
+// | Expansion ROM |       | Expansion ROM |
+// |_______________| $4020 |_______________|
+// | I/O Registers |       |               |
+// |_ _ _ _ _ _ _ _| $4000 |               |
+// | Mirrors       |       | I/O Registers |
+// | $2000-$2007   |       |               |
+// |_ _ _ _ _ _ _ _| $2008 |               |
+// | I/O Registers |       |               |
+// |_______________| $2000 |_______________|
+// | Mirrors       |       |               |
+// | $0000-$07FF   |       |               |
+// |_ _ _ _ _ _ _ _| $0800 |               |
+// | RAM           |       | RAM           |
+// |_ _ _ _ _ _ _ _| $0200 |               |
+// | Stack         |       |               |
+// |_ _ _ _ _ _ _ _| $0100 |               |
+// | Zero Page     |       |               |
+// |_______________| $0000 |_______________|
+
+const RAMSIZE: usize = 0x0800;
+const RAMMIRRORSEND: u16 = 0x1FFF;
+//PPU的8个寄存器每8字节镜像一次，直到$3FFF
+const PPUREGISTERSSTART: u16 = 0x2000;
+const PPUREGISTERSMIRRORSEND: u16 = 0x3FFF;
+const APUREGISTERSSTART: u16 = 0x4000;
+const OAMDMA: u16 = 0x4014;
+const APUSTATUS: u16 = 0x4015;
+const JOYPAD1: u16 = 0x4016;
+const JOYPAD2: u16 = 0x4017;
+//$4018-$401F是CPU测试模式用的寄存器，零售机上没有启用，不注册任何设备
+const CARTRIDGESTART: u16 = 0x4020;
+
+//挂在总线上的设备
+//read/peek返回None表示设备不驱动这个地址，交给先注册的设备，都不驱动时读到open_bus
+//write返回false表示设备不接收这个地址的写入，同样交给先注册的设备
+pub trait Device {
+    fn read(&mut self, add: u16, open_bus: u8) -> Option<u8> {
+        self.peek(add, open_bus)
+    }
+    fn write(&mut self, add: u16, value: u8) -> bool;
+    //没有副作用的读
+    fn peek(&self, add: u16, open_bus: u8) -> Option<u8>;
+    //每个CPU周期调用一次
+    fn tick(&mut self) {}
+    //设备的IRQ输出，总线上所有设备线与
+    fn irq(&self) -> bool {
+        false
+    }
+    fn power_on(&mut self) {}
+    fn reset(&mut self) {}
+}
+
+pub type SharedDevice = Rc<RefCell<dyn Device>>;
+
+//一个设备占用的地址范围，start和end都包含在内
+struct Mapping {
+    start: u16,
+    end: u16,
+    device: SharedDevice,
+}
+
+//2K内部RAM，镜像到$1FFF
+pub struct Ram {
+    memory: [u8; RAMSIZE],
+}
+
+impl Default for Ram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ram {
+    pub fn new() -> Self {
+        Ram {
+            memory: [0; RAMSIZE],
+        }
+    }
+}
+
+impl Device for Ram {
+    fn write(&mut self, add: u16, value: u8) -> bool {
+        self.memory[add as usize % RAMSIZE] = value;
+        true
+    }
+
+    fn peek(&self, add: u16, _open_bus: u8) -> Option<u8> {
+        Some(self.memory[add as usize % RAMSIZE])
+    }
+
+    //复位时RAM保持不变
+    fn power_on(&mut self) {
+        self.memory = [0; RAMSIZE];
+    }
+}
+
+//NES的CPU总线
+//$0000-$1FFF 2K内部RAM及镜像
+//$2000-$3FFF PPU寄存器及镜像
+//$4000-$4017 APU和I/O寄存器，$4014的OAM DMA在CPU内部，由总线直接处理
+//$4018-$401F 测试模式寄存器
+//$4020-$FFFF 卡带，由mapper决定
+//内置部件和外部设备一样注册在地址范围上，后注册的优先
+pub struct Bus {
+    mappings: Vec<Mapping>,
+    devices: Vec<SharedDevice>, //每个设备一项，用于tick、上电和复位
+    ppu: Rc<RefCell<Ppu>>,
+    apu: Rc<RefCell<Apu>>,
+    joypads: Rc<RefCell<Joypads>>,
+    dma_page: Option<u8>, //等待CPU执行的OAM DMA
+    data_bus: u8,         //数据总线上最后一次读写的值，读没有设备驱动的地址时得到它
+    region: Region,       //决定CPU和PPU的主时钟分频
+    master_clock: u64,    //上电以来经过的主时钟周期
+    ppu_clock: u64,       //PPU已经运行到的主时钟周期
+}
+
+impl Bus {
+    //只有PRG ROM的NROM卡带，供测试程序使用
+    pub fn new(prg_rom: Vec<u8>) -> Self {
+        Self::with_mapper(Rc::new(RefCell::new(Nrom::new(
+            prg_rom,
+            vec![],
+            Mirroring::Horizontal,
+        ))))
+    }
+
+    pub fn with_mapper(mapper: SharedMapper) -> Self {
+        let mut bus = Bus {
+            mappings: vec![],
+            devices: vec![],
+            ppu: Rc::new(RefCell::new(Ppu::new(mapper.clone()))),
+            apu: Rc::new(RefCell::new(Apu::new())),
+            joypads: Rc::new(RefCell::new(Joypads::new())),
+            dma_page: None,
+            data_bus: 0,
+            region: Region::default(),
+            master_clock: 0,
+            ppu_clock: 0,
+        };
+        bus.register(0x0000, RAMMIRRORSEND, Rc::new(RefCell::new(Ram::new())));
+        bus.register(PPUREGISTERSSTART, PPUREGISTERSMIRRORSEND, bus.ppu.clone());
+        bus.register(APUREGISTERSSTART, JOYPAD2, bus.apu.clone());
+        //$4017的写入不属于手柄，会落到APU上
+        bus.register(JOYPAD1, JOYPAD2, bus.joypads.clone());
+        bus.register(
+            CARTRIDGESTART,
+            0xFFFF,
+            Rc::new(RefCell::new(Cartridge::new(mapper))),
+        );
+        bus
+    }
+
+    //根据iNES文件头选择mapper
+    pub fn from_rom(rom: Rom) -> Result<Self, RomError> {
+        Ok(Self::with_mapper(mapper_for(rom)?))
+    }
+
+    //把设备注册到start-end（含），覆盖之前注册在这些地址上的设备
+    pub fn register(&mut self, start: u16, end: u16, device: SharedDevice) {
+        if !self.devices.iter().any(|known| Rc::ptr_eq(known, &device)) {
+            self.devices.push(device.clone());
+        }
+        self.mappings.push(Mapping { start, end, device });
+    }
+
+    //当前负责读这个地址的设备，可以用来包装内置部件
+    pub fn device_at(&self, add: u16) -> Option<SharedDevice> {
+        self.mappings_at(add)
+            .next()
+            .map(|mapping| mapping.device.clone())
+    }
+
+    //后注册的在前
+    fn mappings_at(&self, add: u16) -> impl Iterator<Item = &Mapping> {
+        self.mappings
+            .iter()
+            .rev()
+            .filter(move |mapping| (mapping.start..=mapping.end).contains(&add))
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu.borrow_mut().set_region(region);
+        self.apu.borrow_mut().set_region(region);
+    }
+
+    pub fn master_clock(&self) -> u64 {
+        self.master_clock
+    }
+
+    //每个CPU周期调用一次：主时钟前进一个CPU周期，
+    //设备（包括APU）按CPU频率运行，PPU追上主时钟（NTSC 3个点，PAL平均3.2个点）
+    pub fn tick(&mut self) {
+        self.master_clock += self.region.cpu_divider();
+        for device in &self.devices {
+            device.borrow_mut().tick();
+        }
+        let ppu_divider = self.region.ppu_divider();
+        let mut ppu = self.ppu.borrow_mut();
+        while self.ppu_clock + ppu_divider <= self.master_clock {
+            self.ppu_clock += ppu_divider;
+            ppu.tick();
+        }
+    }
+
+    //总线上的IRQ线
+    pub fn irq(&self) -> bool {
+        self.devices.iter().any(|device| device.borrow().irq())
+    }
+
+    pub fn ppu(&self) -> Ref<'_, Ppu> {
+        self.ppu.borrow()
+    }
+
+    pub fn ppu_mut(&self) -> RefMut<'_, Ppu> {
+        self.ppu.borrow_mut()
+    }
+
+    pub fn apu(&self) -> Ref<'_, Apu> {
+        self.apu.borrow()
+    }
+
+    pub fn apu_mut(&self) -> RefMut<'_, Apu> {
+        self.apu.borrow_mut()
+    }
+
+    //index为0是1号手柄（$4016），1是2号手柄（$4017）
+    pub fn joypad_mut(&self, index: usize) -> RefMut<'_, Joypad> {
+        RefMut::map(self.joypads.borrow_mut(), |joypads| {
+            joypads.joypad_mut(index)
+        })
+    }
+}
+
+impl Memory for Bus {
+    fn read(&mut self, add: u16) -> u8 {
+        let open_bus = self.data_bus;
+        let value = self
+            .mappings_at(add)
+            .find_map(|mapping| mapping.device.borrow_mut().read(add, open_bus))
+            .unwrap_or(open_bus);
+        //$4015在CPU内部，不经过外部数据总线，所以不更新锁存的值
+        if add != APUSTATUS {
+            self.data_bus = value;
+        }
+        value
+    }
+
+    fn write(&mut self, add: u16, value: u8) {
+        self.data_bus = value;
+        if add == OAMDMA {
+            self.dma_page = Some(value);
+            return;
+        }
+        for mapping in self.mappings_at(add) {
+            if mapping.device.borrow_mut().write(add, value) {
+                return;
+            }
+        }
+    }
+
+    fn peek(&self, add: u16) -> u8 {
+        self.mappings_at(add)
+            .find_map(|mapping| mapping.device.borrow().peek(add, self.data_bus))
+            .unwrap_or(self.data_bus)
+    }
+
+    //上电清空内部RAM，复位时RAM保持不变
+    //主时钟从0开始计数，接下来复位序列的7个周期照常推动PPU和APU
+    fn power_on(&mut self) {
+        self.dma_page = None;
+        self.data_bus = 0;
+        self.master_clock = 0;
+        self.ppu_clock = 0;
+        for device in &self.devices {
+            device.borrow_mut().power_on();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.dma_page = None;
+        for device in &self.devices {
+            device.borrow_mut().reset();
+        }
+    }
+
+    fn tick(&mut self) {
+        Bus::tick(self);
+    }
+
+    fn take_dma(&mut self) -> Option<u8> {
+        self.dma_page.take()
+    }
+
+    fn nmi(&self) -> bool {
+        self.ppu.borrow().nmi()
+    }
+
+    fn irq(&self) -> bool {
+        Bus::irq(self)
+    }
+
+    fn ppu_position(&self) -> Option<(u16, u16)> {
+        let ppu = self.ppu.borrow();
+        Some((ppu.scanline(), ppu.dot()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::joypads::BUTTONB;
+
+    #[test]
+    fn ram_should_be_mirrored() {
+        let mut bus = Bus::new(vec![]);
+        bus.write(0x0001, 0x55);
+        assert_eq!(bus.read(0x0801), 0x55);
+        assert_eq!(bus.read(0x1801), 0x55);
+        bus.write(0x1fff, 0xaa);
+        assert_eq!(bus.read(0x07ff), 0xaa);
+    }
+
+    #[test]
+    fn single_prg_bank_should_be_mirrored() {
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[0x3ffc] = 0x00;
+        prg_rom[0x3ffd] = 0xc0;
+        let mut bus = Bus::new(prg_rom);
+        assert_eq!(bus.read(0xbffd), 0xc0);
+        assert_eq!(bus.read(0xfffd), 0xc0);
+    }
+
+    #[test]
+    fn reset_should_keep_ram_but_power_on_should_clear_it() {
+        let mut bus = Bus::new(vec![]);
+        bus.write(0x0010, 0x55);
+        bus.ppu_mut().write_register(0x2000, 0x80);
+        bus.reset();
+        assert_eq!(bus.read(0x0010), 0x55);
+        assert_eq!(bus.ppu().ctrl(), 0);
+        bus.power_on();
+        assert_eq!(bus.read(0x0010), 0x00);
+    }
+
+    #[test]
+    fn ppu_registers_should_be_mirrored_every_eight_bytes() {
+        let mut bus = Bus::new(vec![]);
+        bus.write(0x3ff8, 0x80);
+        assert_eq!(bus.ppu().ctrl(), 0x80);
+        bus.write(0x2ffe, 0x21);
+        bus.write(0x2006, 0x00);
+        bus.write(0x3007, 0x55);
+        bus.write(0x2006, 0x21);
+        bus.write(0x200e, 0x00);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x3fff), 0x55);
+    }
+
+    #[test]
+    fn apu_and_joypads_should_be_mapped() {
+        let mut bus = Bus::new(vec![]);
+        bus.write(0x4015, 0b0000_0001);
+        bus.write(0x4003, 0b0000_1000);
+        assert_eq!(bus.read(0x4015), 0b0000_0001);
+        bus.joypad_mut(1).set_button(BUTTONB, true);
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        assert_eq!(bus.read(0x4017), 0);
+        assert_eq!(bus.read(0x4017), 1);
+        assert_eq!(bus.read(0x4016), 0);
+    }
+
+    #[test]
+    fn cartridge_space_should_be_delegated_to_mapper() {
+        let mut bus = Bus::new(vec![0x11; 0x8000]);
+        bus.write(0x6000, 0x22);
+        assert_eq!(bus.read(0x6000), 0x22);
+        //测试模式寄存器没有启用，读到的是刚写入的值
+        bus.write(0x401f, 0x33);
+        assert_eq!(bus.read(0x401f), 0x33);
+    }
+
+    #[test]
+    fn oam_dma_should_copy_page_and_stall_cpu() {
+        //LDA #$02; STA $4014; LDA $00; STA $4014
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[..10]
+            .copy_from_slice(&[0xa9, 0x02, 0x8d, 0x14, 0x40, 0xa5, 0x00, 0x8d, 0x14, 0x40]);
+        prg_rom[0x7ffd] = 0x80;
+        let mut cpu = CPU::with_memory(Bus::new(prg_rom));
+        cpu.power_on();
+        for offset in 0..=0xff {
+            cpu.write_to_memory_u8(0x0200 + offset, offset as u8);
+        }
+        cpu.write_to_memory_u8(0x0000, 0x02);
+        cpu.step().unwrap();
+        //STA在第13个周期结束，奇数周期需要多等一个周期
+        assert_eq!(cpu.cycles(), 9);
+        assert_eq!(cpu.step().unwrap().cycles, 4 + 514);
+        assert_eq!(cpu.memory().ppu().oam()[0x10], 0x10);
+        assert_eq!(cpu.memory().ppu().oam()[0xff], 0xff);
+        cpu.step().unwrap();
+        assert_eq!(cpu.step().unwrap().cycles, 4 + 513);
+    }
+
+    #[test]
+    fn reset_sequence_should_clock_ppu() {
+        //JMP $8000
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[..3].copy_from_slice(&[0x4c, 0x00, 0x80]);
+        prg_rom[0x7ffd] = 0x80;
+        let mut cpu = CPU::with_memory(Bus::new(prg_rom));
+        cpu.run_for_cycles(1000).unwrap();
+        cpu.power_on();
+        assert_eq!(cpu.memory().master_clock(), 7 * 12);
+        assert_eq!(cpu.memory().ppu().scanline(), 0);
+        assert_eq!(cpu.memory().ppu().dot(), 7 * 3);
+        cpu.run_for_cycles(50).unwrap();
+        //复位不改变主时钟和PPU的位置，复位序列让它们再前进7个CPU周期
+        let dots = cpu.memory().ppu().dot();
+        let master_clock = cpu.memory().master_clock();
+        cpu.reset();
+        assert_eq!(cpu.memory().master_clock(), master_clock + 7 * 12);
+        assert_eq!(cpu.memory().master_clock(), cpu.cycles() * 12);
+        assert_eq!(cpu.memory().ppu().dot(), dots + 7 * 3);
+    }
+
+    #[test]
+    fn unmapped_reads_should_return_last_bus_value() {
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[2] = 0x40;
+        let mut bus = Bus::new(prg_rom);
+        bus.write(0x0010, 0x55);
+        assert_eq!(bus.read(0x4000), 0x55);
+        assert_eq!(bus.read(0x5000), 0x55);
+        assert_eq!(bus.read(0x401f), 0x55);
+        //LDA $4016：最后一次读到的是操作数高字节$40
+        bus.joypad_mut(0).set_button(BUTTONB, true);
+        bus.write(0x4016, 1);
+        bus.read(0x8002);
+        assert_eq!(bus.read(0x4016), 0x40);
+        assert_eq!(bus.peek(0x4016), 0x40);
+    }
+
+    #[test]
+    fn apu_status_read_should_not_update_bus() {
+        let mut bus = Bus::new(vec![]);
+        bus.write(0x0010, 0xff);
+        assert_eq!(bus.read(0x0010), 0xff);
+        //第5位没有驱动
+        assert_eq!(bus.read(0x4015), 0b0010_0000);
+        assert_eq!(bus.read(0x4000), 0xff);
+    }
+
+    //调试用的设备：记录写入和tick，读转发给被包装的设备，没有时返回固定值
+    struct Probe {
+        inner: Option<SharedDevice>,
+        writes: Vec<(u16, u8)>,
+        ticks: u32,
+        irq: bool,
+    }
+
+    impl Probe {
+        fn new(inner: Option<SharedDevice>) -> Rc<RefCell<Probe>> {
+            Rc::new(RefCell::new(Probe {
+                inner,
+                writes: vec![],
+                ticks: 0,
+                irq: false,
+            }))
+        }
+    }
+
+    impl Device for Probe {
+        fn write(&mut self, add: u16, value: u8) -> bool {
+            self.writes.push((add, value));
+            match &self.inner {
+                Some(inner) => inner.borrow_mut().write(add, value),
+                None => true,
+            }
+        }
+
+        fn peek(&self, add: u16, open_bus: u8) -> Option<u8> {
+            match &self.inner {
+                Some(inner) => inner.borrow().peek(add, open_bus),
+                None => Some(0x5a),
+            }
+        }
+
+        fn tick(&mut self) {
+            self.ticks += 1;
+        }
+
+        fn irq(&self) -> bool {
+            self.irq
+        }
+    }
+
+    #[test]
+    fn registered_device_should_handle_its_range() {
+        let mut bus = Bus::new(vec![]);
+        let probe = Probe::new(None);
+        bus.register(0x5000, 0x5fff, probe.clone());
+        assert_eq!(bus.read(0x5000), 0x5a);
+        assert_eq!(bus.peek(0x5fff), 0x5a);
+        bus.write(0x5001, 0x11);
+        assert_eq!(probe.borrow().writes, vec![(0x5001, 0x11)]);
+        bus.tick();
+        bus.tick();
+        assert_eq!(probe.borrow().ticks, 2);
+        assert!(!bus.irq());
+        probe.borrow_mut().irq = true;
+        assert!(bus.irq());
+    }
+
+    #[test]
+    fn builtin_device_should_be_wrappable() {
+        let mut bus = Bus::new(vec![]);
+        let ram = bus.device_at(0x0000).unwrap();
+        let probe = Probe::new(Some(ram));
+        bus.register(0x0000, RAMMIRRORSEND, probe.clone());
+        bus.write(0x0801, 0x77);
+        assert_eq!(bus.read(0x0001), 0x77);
+        assert_eq!(probe.borrow().writes, vec![(0x0801, 0x77)]);
+        //同一个设备只tick一次
+        bus.register(0x6000, 0x6000, probe.clone());
+        bus.tick();
+        assert_eq!(probe.borrow().ticks, 1);
+    }
+
+    #[test]
+    fn prg_rom_should_ignore_writes() {
+        let mut bus = Bus::new(vec![0x11; 0x8000]);
+        bus.write(0x8000, 0x22);
+        assert_eq!(bus.read(0x8000), 0x11);
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+
+//iNES文件格式：16字节文件头，可选的512字节trainer，然后是PRG ROM和CHR ROM
+const NESTAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADERSIZE: usize = 16;
+const TRAINERSIZE: usize = 512;
+const PRGROMPAGESIZE: usize = 0x4000;
+const CHRROMPAGESIZE: usize = 0x2000;
+//第7字节的第2、3位：10表示NES 2.0，00是iNES，01是早期的iNES
+const VERSIONMASK: u8 = 0b0000_1100;
+const VERSIONNES2: u8 = 0b0000_1000;
+const VERSIONINES: u8 = 0b0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomError {
+    //文件头不是NES<EOF>
+    InvalidHeader,
+    //文件长度小于文件头声明的大小
+    Truncated { expected: usize, actual: usize },
+    //还没有实现的mapper
+    UnsupportedMapper(u8),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::InvalidHeader => write!(f, "file is not in iNES format"),
+            RomError::Truncated { expected, actual } => {
+                write!(f, "rom needs {} bytes but file has {}", expected, actual)
+            }
+            RomError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u8,
+    pub screen_mirroring: Mirroring,
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        if raw.len() < HEADERSIZE || raw[0..4] != NESTAG {
+            return Err(RomError::InvalidHeader);
+        }
+        //NES 2.0按兼容iNES的方式解析，忽略第8字节的mapper高位和第9字节的容量高位
+        //早期的文件第7-15字节可能是垃圾（例如"DiskDude!"），这时第7字节不可信
+        let flags7 = match raw[7] & VERSIONMASK {
+            VERSIONNES2 => raw[7],
+            VERSIONINES if raw[12..HEADERSIZE].iter().all(|byte| *byte == 0) => raw[7],
+            _ => 0,
+        };
+        //mapper编号的低4位在第6字节高4位，高4位在第7字节高4位
+        let mapper = (flags7 & 0b1111_0000) | (raw[6] >> 4);
+        let screen_mirroring = match (raw[6] & 0b1000 != 0, raw[6] & 0b1 != 0) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let prg_rom_size = raw[4] as usize * PRGROMPAGESIZE;
+        let chr_rom_size = raw[5] as usize * CHRROMPAGESIZE;
+        let skip_trainer = raw[6] & 0b100 != 0;
+        let prg_rom_start = HEADERSIZE + if skip_trainer { TRAINERSIZE } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+        let end = chr_rom_start + chr_rom_size;
+        if raw.len() < end {
+            return Err(RomError::Truncated {
+                expected: end,
+                actual: raw.len(),
+            });
+        }
+
+        Ok(Rom {
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..end].to_vec(),
+            mapper,
+            screen_mirroring,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ines(flags6: u8, flags7: u8, prg_pages: u8, chr_pages: u8) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, prg_pages, chr_pages, flags6, flags7];
+        raw.resize(HEADERSIZE, 0);
+        raw.resize(
+            HEADERSIZE + prg_pages as usize * PRGROMPAGESIZE + chr_pages as usize * CHRROMPAGESIZE,
+            0,
+        );
+        raw
+    }
+
+    #[test]
+    fn rom_should_parse_header() {
+        let mut raw = ines(0x31, 0x10, 2, 1);
+        raw[HEADERSIZE] = 0xaa;
+        raw[HEADERSIZE + 2 * PRGROMPAGESIZE] = 0xbb;
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 0x13);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert_eq!(rom.prg_rom.len(), 2 * PRGROMPAGESIZE);
+        assert_eq!(rom.prg_rom[0], 0xaa);
+        assert_eq!(rom.chr_rom[0], 0xbb);
+    }
+
+    #[test]
+    fn rom_should_parse_nes2_header_as_ines() {
+        //mapper 0x113：第8字节的高位被忽略；第9字节的容量高位同样忽略
+        let mut raw = ines(0x30, 0x18, 1, 1);
+        raw[8] = 0x01;
+        raw[9] = 0x11;
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 0x13);
+        assert_eq!(rom.prg_rom.len(), PRGROMPAGESIZE);
+        assert_eq!(rom.chr_rom.len(), CHRROMPAGESIZE);
+    }
+
+    #[test]
+    fn rom_should_ignore_byte_7_of_archaic_header() {
+        //第7字节的版本位为01
+        let rom = Rom::new(&ines(0x10, 0x44, 1, 0)).unwrap();
+        assert_eq!(rom.mapper, 0x01);
+        //"DiskDude!"写在第7-15字节
+        let mut raw = ines(0x40, 0, 1, 0);
+        raw[7..HEADERSIZE].copy_from_slice(b"DiskDude!");
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 0x04);
+        assert_eq!(rom.prg_rom.len(), PRGROMPAGESIZE);
+    }
+
+    #[test]
+    fn rom_should_reject_bad_files() {
+        assert_eq!(Rom::new(&[0; 16]).err(), Some(RomError::InvalidHeader));
+        let mut raw = ines(0, 0, 1, 1);
+        raw.truncate(HEADERSIZE + 10);
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::Truncated {
+                expected: HEADERSIZE + PRGROMPAGESIZE + CHRROMPAGESIZE,
+                actual: HEADERSIZE + 10
+            })
+        );
+    }
+}
@@ -1,44 +1,453 @@
 use crate::cpuoperand::AddressingModes;
-use crate::cpuoperand::CPU_OPRAND_HASHMAP;
+use crate::cpuoperand::OpCode;
+use crate::cpuoperand::CPU_OPRAND_TABLE;
+use crate::memory::{FlatMemory, Memory, MEMORYSIZE};
+use lazy_static::lazy_static;
+use std::fmt;
+use std::io::{self, Write};
 
 const PROGRAMSTARTADDRESS: u16 = 0x8000;
-const MEMORYSIZE: usize = 0xFFFF;
+const NMIADDRESS: u16 = 0xFFFA;
 const RESETADDRESS: u16 = 0xFFFC;
-//const STACKPOINTERSTART: u16 = 0x0100;
+const IRQADDRESS: u16 = 0xFFFE;
+const STACKPOINTERSTART: u16 = 0x0100;
 const STACKRESET: u8 = 0xFD;
+//复位序列像中断一样执行三次压栈，但不写总线
+const RESETSTACKDECREMENT: u8 = 3;
+//调用栈判断返回地址是否出栈时，栈指针往回移动半页以内都算出栈
+const CALLSTACKMINDEPTH: u8 = 0x80;
+//256字节的栈最多放下128个返回地址，更早的帧已经被覆盖
+const CALLSTACKMAXFRAMES: usize = 128;
+//每个CPU周期对应3个PPU点，每条扫描线341个点，每帧262条扫描线
+const PPUDOTSPERCYCLE: u64 = 3;
+const PPUDOTSPERSCANLINE: u64 = 341;
+const PPUSCANLINESPERFRAME: u64 = 262;
+//不稳定指令XAA/LXA中与芯片有关的常数
+const UNSTABLEMAGIC: u8 = 0xEE;
+//OAM DMA把一页256字节逐个写到PPU的OAMDATA
+const OAMDATA: u16 = 0x2004;
+const OAMDMABYTES: u16 = 256;
 
+//状态寄存器中真实存在的六个标志位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StatusType {
     NegativeFlag,
     OverflowFlag,
-    Break,
-    Break2,
     DecimalModeFlag,
     InterruptDisable,
     ZeroFlag,
     CarryFlag,
 }
 
-pub struct CPU {
-    register_a: u8, //a寄存器，累加器
-    register_x: u8, //x寄存器
-    register_y: u8, //y寄存器
-    //(从7 [最高]到0 [最低])： NV-BDIZC
-    status: u8,               //标志位
-    program_counter: u16,     //程序计数器
-    memory: [u8; MEMORYSIZE], //内存
+impl StatusType {
+    fn mask(self) -> u8 {
+        match self {
+            StatusType::NegativeFlag => 0b1000_0000,
+            StatusType::OverflowFlag => 0b0100_0000,
+            StatusType::DecimalModeFlag => 0b0000_1000,
+            StatusType::InterruptDisable => 0b0000_0100,
+            StatusType::ZeroFlag => 0b0000_0010,
+            StatusType::CarryFlag => 0b0000_0001,
+        }
+    }
+}
+
+//(从7 [最高]到0 [最低])： NV-BDIZC
+//寄存器里只有NV和DIZC，B和第5位只出现在压栈的副本里：
+//PHP/BRK压入时两位都为1，IRQ/NMI压入时只有第5位为1，PLP/RTI出栈时忽略这两位
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Status(u8);
+
+const STATUSBREAK: u8 = 0b0001_0000;
+const STATUSUNUSED: u8 = 0b0010_0000;
+
+impl Status {
+    //出栈或外部设置时丢掉B和第5位
+    pub fn from_bits(bits: u8) -> Self {
+        Status(bits & !(STATUSBREAK | STATUSUNUSED))
+    }
+
+    //读出的值，第5位恒为1，B为0，与nestest日志中的P一致
+    pub fn bits(self) -> u8 {
+        self.0 | STATUSUNUSED
+    }
+
+    //压栈的值，brk为true表示PHP/BRK，false表示IRQ/NMI
+    pub fn pushed(self, brk: bool) -> u8 {
+        if brk {
+            self.0 | STATUSUNUSED | STATUSBREAK
+        } else {
+            self.0 | STATUSUNUSED
+        }
+    }
+
+    pub fn get(self, flag: StatusType) -> bool {
+        self.0 & flag.mask() != 0
+    }
+
+    pub fn set(&mut self, flag: StatusType, value: bool) {
+        if value {
+            self.0 |= flag.mask();
+        } else {
+            self.0 &= !flag.mask();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    //操作码在分派表中没有定义
+    UnknownOpcode { opcode: u8, address: u16 },
+    //执行了KIL，CPU停机，只有复位能恢复
+    Jammed { opcode: u8, address: u16 },
+    //指令使用了无法计算地址的寻址方式
+    InvalidAddressingMode(AddressingModes),
+    //程序超出了可以装载的空间，例如0x8000以上的空间
+    ProgramTooLarge { size: usize, max: usize },
+    //程序写不进这个地址，例如总线上的卡带ROM
+    ReadOnlyMemory(u16),
+    //写跟踪日志失败
+    TraceFailed(io::ErrorKind),
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { opcode, address } => {
+                write!(f, "unknown opcode {:02X} at {:04X}", opcode, address)
+            }
+            CpuError::Jammed { opcode, address } => {
+                write!(f, "cpu jammed by opcode {:02X} at {:04X}", opcode, address)
+            }
+            CpuError::InvalidAddressingMode(mode) => {
+                write!(f, "addressing mode {:?} has no operand address", mode)
+            }
+            CpuError::ProgramTooLarge { size, max } => {
+                write!(f, "program of {} bytes exceeds {} bytes", size, max)
+            }
+            CpuError::ReadOnlyMemory(address) => {
+                write!(f, "cannot load program into read-only {:04X}", address)
+            }
+            CpuError::TraceFailed(kind) => write!(f, "failed to write trace: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepEvent {
+    Normal,  //正常执行
+    Break,   //执行了BRK
+    Trapped, //指令跳转到自身，程序陷入死循环
+    Halted,  //执行了KIL，CPU停机
+}
+
+//CPU型号：NES的2A03去掉了十进制运算，标准NMOS 6502在D标志为1时ADC/SBC按BCD运算
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    #[default]
+    Ricoh2A03,
+    Nmos6502,
+}
+
+//寄存器快照，供调试器和测试读取、设置CPU状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub register_a: u8,
+    pub register_x: u8,
+    pub register_y: u8,
+    pub status: u8,
+    pub stack_pointer: u8,
+    pub program_counter: u16,
+}
+
+//调用栈的一帧，由JSR压入的返回地址推出，供调试器显示
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    pub caller: u16,         //JSR指令所在地址
+    pub target: u16,         //子程序入口
+    pub return_address: u16, //RTS之后继续执行的地址
+    pub stack_pointer: u8,   //压入返回地址之后的栈指针
+}
+
+//step的返回值，描述这一步发生了什么
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepResult {
+    pub address: u16,                 //指令所在地址
+    pub opcode: u8,                   //执行的操作码
+    pub cycles: u64,                  //这一步消耗的周期数，包括中断响应
+    pub interrupt: Option<Interrupt>, //执行指令前响应的中断
+    pub event: StepEvent,
+}
+
+//分派表的一项：操作 + 操作码信息（寻址方式、字节数、周期数）
+#[derive(Clone, Copy)]
+pub struct Instruction {
+    pub opcode: &'static OpCode,
+    operation: Operation,
+}
+
+//指令要执行的操作，由助记符决定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Adc,
+    And,
+    Asl,
+    Bcc,
+    Bcs,
+    Beq,
+    Bit,
+    Bmi,
+    Bne,
+    Bpl,
+    Brk,
+    Bvc,
+    Bvs,
+    Clc,
+    Cld,
+    Cli,
+    Clv,
+    Cmp,
+    Cpx,
+    Cpy,
+    Dec,
+    Dex,
+    Dey,
+    Eor,
+    Inc,
+    Inx,
+    Iny,
+    Jmp,
+    Jsr,
+    Lda,
+    Ldx,
+    Ldy,
+    Lsr,
+    Nop,
+    Ora,
+    Pha,
+    Php,
+    Pla,
+    Plp,
+    Rol,
+    Ror,
+    Rti,
+    Rts,
+    Sbc,
+    Sec,
+    Sed,
+    Sei,
+    Sta,
+    Stx,
+    Sty,
+    Tax,
+    Tay,
+    Tsx,
+    Txa,
+    Txs,
+    Tya,
+    Lax,
+    Sax,
+    Dcp,
+    Isb,
+    Slo,
+    Rla,
+    Sre,
+    Rra,
+    Anc,
+    Alr,
+    Arr,
+    Axs,
+    Xaa,
+    Lxa,
+    Ahx,
+    Shx,
+    Shy,
+    Tas,
+    Las,
+    Kil,
+}
+
+//助记符到操作的映射，只在建表时使用一次
+fn operation_for(opname: &str) -> Operation {
+    match opname {
+        "ADC" => Operation::Adc,
+        "AND" => Operation::And,
+        "ASL" => Operation::Asl,
+        "BCC" => Operation::Bcc,
+        "BCS" => Operation::Bcs,
+        "BEQ" => Operation::Beq,
+        "BIT" => Operation::Bit,
+        "BMI" => Operation::Bmi,
+        "BNE" => Operation::Bne,
+        "BPL" => Operation::Bpl,
+        "BRK" => Operation::Brk,
+        "BVC" => Operation::Bvc,
+        "BVS" => Operation::Bvs,
+        "CLC" => Operation::Clc,
+        "CLD" => Operation::Cld,
+        "CLI" => Operation::Cli,
+        "CLV" => Operation::Clv,
+        "CMP" => Operation::Cmp,
+        "CPX" => Operation::Cpx,
+        "CPY" => Operation::Cpy,
+        "DEC" => Operation::Dec,
+        "DEX" => Operation::Dex,
+        "DEY" => Operation::Dey,
+        "EOR" => Operation::Eor,
+        "INC" => Operation::Inc,
+        "INX" => Operation::Inx,
+        "INY" => Operation::Iny,
+        "JMP" => Operation::Jmp,
+        "JSR" => Operation::Jsr,
+        "LDA" => Operation::Lda,
+        "LDX" => Operation::Ldx,
+        "LDY" => Operation::Ldy,
+        "LSR" => Operation::Lsr,
+        "NOP" => Operation::Nop,
+        "ORA" => Operation::Ora,
+        "PHA" => Operation::Pha,
+        "PHP" => Operation::Php,
+        "PLA" => Operation::Pla,
+        "PLP" => Operation::Plp,
+        "ROL" => Operation::Rol,
+        "ROR" => Operation::Ror,
+        "RTI" => Operation::Rti,
+        "RTS" => Operation::Rts,
+        "SBC" => Operation::Sbc,
+        "SEC" => Operation::Sec,
+        "SED" => Operation::Sed,
+        "SEI" => Operation::Sei,
+        "STA" => Operation::Sta,
+        "STX" => Operation::Stx,
+        "STY" => Operation::Sty,
+        "TAX" => Operation::Tax,
+        "TAY" => Operation::Tay,
+        "TSX" => Operation::Tsx,
+        "TXA" => Operation::Txa,
+        "TXS" => Operation::Txs,
+        "TYA" => Operation::Tya,
+        "LAX" => Operation::Lax,
+        "SAX" => Operation::Sax,
+        "DCP" => Operation::Dcp,
+        "ISB" => Operation::Isb,
+        "SLO" => Operation::Slo,
+        "RLA" => Operation::Rla,
+        "SRE" => Operation::Sre,
+        "RRA" => Operation::Rra,
+        "ANC" => Operation::Anc,
+        "ALR" => Operation::Alr,
+        "ARR" => Operation::Arr,
+        "AXS" => Operation::Axs,
+        "XAA" => Operation::Xaa,
+        "LXA" => Operation::Lxa,
+        "AHX" => Operation::Ahx,
+        "SHX" => Operation::Shx,
+        "SHY" => Operation::Shy,
+        "TAS" => Operation::Tas,
+        "LAS" => Operation::Las,
+        "KIL" => Operation::Kil,
+        _ => panic!("no handler for {}", opname),
+    }
+}
+
+lazy_static! {
+    //256项分派表，按操作码字节直接索引，运行时不做哈希和字符串比较
+    pub static ref INSTRUCTION_TABLE: [Option<Instruction>; 256] = {
+        let mut table = [None; 256];
+        for (code, opcode) in CPU_OPRAND_TABLE.iter().enumerate() {
+            if let Some(opcode) = opcode {
+                table[code] = Some(Instruction {
+                    opcode,
+                    operation: operation_for(opcode.opname),
+                });
+            }
+        }
+        table
+    };
+}
+
+//写和读改写指令在变址寻址时不论是否跨页都会先空读一次
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+pub struct CPU<M: Memory = FlatMemory> {
+    register_a: u8,       //a寄存器，累加器
+    register_x: u8,       //x寄存器
+    register_y: u8,       //y寄存器
+    status: Status,       //标志位
+    program_counter: u16, //程序计数器
+    memory: M,            //总线，CPU的所有读写都经过它
     stack_pointer: u8,
+    cycles: u64,                    //已执行的周期数，供PPU/APU同步
+    page_crossed: bool,             //最近一次变址寻址是否跨页
+    jammed: bool,                   //执行了KIL，CPU停机
+    nmi_line: bool,                 //NMI输入线当前电平
+    nmi_input: bool,                //上次采样时NMI输入线和总线NMI合起来的电平
+    nmi_pending: bool,              //检测到NMI边沿，等待响应
+    irq_line: bool,                 //IRQ输入线，电平触发
+    interrupt_sampled: bool,        //最近一个周期结束时是否有中断请求
+    interrupt_polled: bool,         //上一个周期的采样，指令结束时据此响应中断
+    variant: Variant,               //构造时确定，决定是否支持十进制运算
+    tracer: Option<Box<dyn Write>>, //每条指令执行前写一行nestest格式的日志
+    call_stack: Vec<CallFrame>,     //JSR留在栈上、还没有弹出的返回地址
+}
+
+//两个地址是否位于不同的页
+fn page_crossed(a: u16, b: u16) -> bool {
+    a & 0xff00 != b & 0xff00
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CPU {
+    //使用平坦的64K内存
     pub fn new() -> Self {
+        Self::with_memory(FlatMemory::new())
+    }
+}
+
+impl<M: Memory> CPU<M> {
+    //默认是NES的2A03
+    pub fn with_memory(memory: M) -> Self {
+        Self::with_variant(memory, Variant::default())
+    }
+
+    pub fn with_variant(memory: M, variant: Variant) -> Self {
         CPU {
             register_a: 0,
             register_x: 0,
             register_y: 0,
-            status: 0,
+            status: Status::default(),
             program_counter: 0,
-            memory: [0; MEMORYSIZE],
+            memory,
             stack_pointer: STACKRESET,
+            cycles: 0,
+            page_crossed: false,
+            jammed: false,
+            nmi_line: false,
+            nmi_input: false,
+            nmi_pending: false,
+            irq_line: false,
+            interrupt_sampled: false,
+            interrupt_polled: false,
+            variant,
+            tracer: None,
+            call_stack: vec![],
         }
     }
     //NES uses little endian
@@ -50,184 +459,173 @@ impl CPU {
     // $FFFC and $FFFD	Reset
     // $FFFE and $FFFF	IRQ/BRK
 
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut M {
+        &mut self.memory
+    }
+
+    //直接读写总线，不占用周期；读不会产生副作用，供调试器和测试使用
     pub fn write_to_memory_u8(&mut self, add: u16, value: u8) {
-        self.memory[add as usize] = value;
+        self.memory.write(add, value);
     }
     pub fn read_from_memory_u8(&self, add: u16) -> u8 {
-        self.memory[add as usize]
+        self.memory.peek(add)
     }
 
     pub fn write_to_memory_u16(&mut self, add: u16, value: u16) {
         let low = (value & 0x00ff) as u8;
         let high = (value >> 8) as u8;
         self.write_to_memory_u8(add, low);
-        self.write_to_memory_u8(add + 1, high);
+        self.write_to_memory_u8(add.wrapping_add(1), high);
     }
 
     pub fn read_from_memory_u16(&self, add: u16) -> u16 {
         let low = self.read_from_memory_u8(add) as u16;
-        let high = self.read_from_memory_u8(add + 1) as u16;
+        let high = self.read_from_memory_u8(add.wrapping_add(1)) as u16;
         (high << 8) | low
     }
 
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
     pub fn setstatus(&mut self, statype: StatusType, flag: bool) {
-        match statype {
-            StatusType::NegativeFlag => {
-                if flag {
-                    self.status = self.status | 0b1000_0000;
-                } else {
-                    self.status = self.status & 0b0111_1111;
-                }
-            }
-            StatusType::OverflowFlag => {
-                if flag {
-                    self.status = self.status | 0b0100_0000;
-                } else {
-                    self.status = self.status & 0b1011_1111;
-                }
-            }
-            StatusType::Break2 => {
-                if flag {
-                    self.status = self.status | 0b0010_0000;
-                } else {
-                    self.status = self.status & 0b1101_1111;
-                }
-            }
-            StatusType::Break => {
-                if flag {
-                    self.status = self.status | 0b0001_0000;
-                } else {
-                    self.status = self.status & 0b1110_1111;
-                }
-            }
-            StatusType::DecimalModeFlag => {
-                if flag {
-                    self.status = self.status | 0b0000_1000;
-                } else {
-                    self.status = self.status & 0b1111_0111;
-                }
-            }
-            StatusType::InterruptDisable => {
-                if flag {
-                    self.status = self.status | 0b0000_0100;
-                } else {
-                    self.status = self.status & 0b1111_1011;
-                }
-            }
-            StatusType::ZeroFlag => {
-                if flag {
-                    self.status = self.status | 0b0000_0010;
-                } else {
-                    self.status = self.status & 0b1111_1101;
-                }
-            }
-            StatusType::CarryFlag => {
-                if flag {
-                    self.status = self.status | 0b0000_0001;
-                } else {
-                    self.status = self.status & 0b1111_1110;
-                }
-            }
-        }
+        self.status.set(statype, flag);
     }
 
-    pub fn getstatus(&mut self, statype: StatusType) -> bool {
-        match statype {
-            StatusType::NegativeFlag => {
-                let tempstatus = self.status;
-                (tempstatus & 0b1000_0000) >> 7 == 1
-            }
-            StatusType::OverflowFlag => {
-                let tempstatus = self.status;
-                (tempstatus & 0b0100_0000) >> 6 == 1
-            }
-            StatusType::Break2 => {
-                let tempstatus = self.status;
-                (tempstatus & 0b0010_0000) >> 5 == 1
-            }
-            StatusType::Break => {
-                let tempstatus = self.status;
-                (tempstatus & 0b0001_0000) >> 4 == 1
-            }
-            StatusType::DecimalModeFlag => {
-                let tempstatus = self.status;
-                (tempstatus & 0b0000_1000) >> 3 == 1
-            }
-            StatusType::InterruptDisable => {
-                let tempstatus = self.status;
-                (tempstatus & 0b0000_0100) >> 2 == 1
-            }
-            StatusType::ZeroFlag => {
-                let tempstatus = self.status;
-                (tempstatus & 0b0000_0010) >> 1 == 1
-            }
-            StatusType::CarryFlag => {
-                let tempstatus = self.status;
-                (tempstatus & 0b0000_0001) == 1
-            }
-        }
+    pub fn getstatus(&self, statype: StatusType) -> bool {
+        self.status.get(statype)
     }
 
-    pub fn get_operand_address(&mut self, mode: &AddressingModes) -> u16 {
-        match mode {
+    //每次总线读写占用一个周期，周期结束时采样中断
+    fn bus_read(&mut self, add: u16) -> u8 {
+        self.cycles += 1;
+        let value = self.memory.read(add);
+        self.memory.tick();
+        self.sample_interrupts();
+        value
+    }
+
+    fn bus_write(&mut self, add: u16, value: u8) {
+        self.cycles += 1;
+        self.memory.write(add, value);
+        self.memory.tick();
+        self.sample_interrupts();
+    }
+
+    //读取PC处的字节，PC加一
+    fn fetch(&mut self) -> u8 {
+        let value = self.bus_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        value
+    }
+
+    fn fetch_u16(&mut self) -> u16 {
+        let low = self.fetch() as u16;
+        let high = self.fetch() as u16;
+        (high << 8) | low
+    }
+
+    //零页指针的高字节在$FF处回绕到$00
+    fn read_zero_page_pointer(&mut self, pointer: u8) -> u16 {
+        let low = self.bus_read(pointer as u16) as u16;
+        let high = self.bus_read(pointer.wrapping_add(1) as u16) as u16;
+        (high << 8) | low
+    }
+
+    //读指令的操作数地址
+    pub fn get_operand_address(&mut self, mode: &AddressingModes) -> Result<u16, CpuError> {
+        self.operand_address(mode, Access::Read)
+    }
+
+    //写和读改写指令的操作数地址
+    fn get_write_address(&mut self, mode: &AddressingModes) -> Result<u16, CpuError> {
+        self.operand_address(mode, Access::Write)
+    }
+
+    //按硬件的总线时序取操作数并计算地址，执行后PC指向下一条指令
+    fn operand_address(&mut self, mode: &AddressingModes, access: Access) -> Result<u16, CpuError> {
+        self.page_crossed = false;
+        let addr = match mode {
             //立即数，本质是一个数
-            AddressingModes::Immediate => self.program_counter,
+            AddressingModes::Immediate => {
+                let addr = self.program_counter;
+                self.program_counter = self.program_counter.wrapping_add(1);
+                addr
+            }
             //八位地址
-            AddressingModes::ZeroPage => self.read_from_memory_u8(self.program_counter) as u16,
+            AddressingModes::ZeroPage => self.fetch() as u16,
+            //变址结果在零页内回绕，加变址寄存器时先空读一次基址
             AddressingModes::ZeroPageX => {
                 //读取参数
-                let para = self.read_from_memory_u8(self.program_counter) as u16;
-                let addr = para.wrapping_add(self.register_x as u16);
-                addr
+                let para = self.fetch();
+                self.bus_read(para as u16);
+                para.wrapping_add(self.register_x) as u16
             }
             AddressingModes::ZeroPageY => {
-                let para = self.read_from_memory_u8(self.program_counter) as u16;
-                let addr = para.wrapping_add(self.register_x as u16);
-                addr
+                let para = self.fetch();
+                self.bus_read(para as u16);
+                para.wrapping_add(self.register_y) as u16
             }
+            //有符号偏移量，相对于下一条指令的地址
             AddressingModes::Relative => {
-                let para = self.read_from_memory_u8(self.program_counter) as u16;
-                let addr = self.program_counter + para + 1;
-                addr
+                let para = self.fetch() as i8;
+                self.program_counter.wrapping_add(para as u16)
             }
             //16位地址
-            AddressingModes::Absolute => self.read_from_memory_u16(self.program_counter),
+            AddressingModes::Absolute => self.fetch_u16(),
             AddressingModes::AbsoluteX => {
-                let para = self.read_from_memory_u16(self.program_counter);
-                let addr = para.wrapping_add(self.register_x as u16);
-                addr
+                let para = self.fetch_u16();
+                self.indexed_address(para, self.register_x, access)
             }
             AddressingModes::AbsoluteY => {
-                let para = self.read_from_memory_u16(self.program_counter);
-                let addr = para.wrapping_add(self.register_y as u16);
-                addr
+                let para = self.fetch_u16();
+                self.indexed_address(para, self.register_y, access)
             }
+            //JMP ($xxFF)的硬件缺陷：高字节从同一页的$xx00读取，不会进位到下一页
             AddressingModes::Indirect => {
-                let para = self.read_from_memory_u16(self.program_counter);
-                let low = self.read_from_memory_u8(para) as u16;
-                let high = self.read_from_memory_u8(para.wrapping_add(1)) as u16;
-                (high << 8) | (low & 0x00ff)
+                let para = self.fetch_u16();
+                let low = self.bus_read(para) as u16;
+                let high_addr = (para & 0xff00) | (para.wrapping_add(1) & 0x00ff);
+                let high = self.bus_read(high_addr) as u16;
+                (high << 8) | low
             }
-            //indirect x,先把操作数加上 x获得地址，再去读地址、地址+1
+            //indirect x,先把零页操作数加上 x获得指针，再去读指针、指针+1，指针在零页内回绕
             AddressingModes::IndexedIndirect => {
-                let para = self.read_from_memory_u16(self.program_counter);
-                let base = para + (self.register_x as u16);
-                let low = self.read_from_memory_u8(base) as u16;
-                let high = self.read_from_memory_u8(base.wrapping_add(1)) as u16;
-                (high << 8) | (low & 0x00ff)
+                let para = self.fetch();
+                self.bus_read(para as u16);
+                let pointer = para.wrapping_add(self.register_x);
+                self.read_zero_page_pointer(pointer)
             }
-            //indirect y，先读操作数即地址、地址+1,再将读出来的加上y
+            //indirect y，先读零页指针、指针+1,再将读出来的加上y
             AddressingModes::IndirectIndexed => {
-                let para = self.read_from_memory_u16(self.program_counter);
-                let low = self.read_from_memory_u8(para) as u16;
-                let high = self.read_from_memory_u8(para.wrapping_add(1)) as u16;
-                let addr = (high << 8) | (low & 0x00ff);
-                addr.wrapping_add(self.register_y as u16)
+                let para = self.fetch();
+                let base = self.read_zero_page_pointer(para);
+                self.indexed_address(base, self.register_y, access)
             }
-            AddressingModes::NoAddressingMode => {
-                panic!("undefined mode :{:?}", mode);
+            AddressingModes::Accumulator | AddressingModes::NoAddressingMode => {
+                return Err(CpuError::InvalidAddressingMode(*mode));
             }
+        };
+        Ok(addr)
+    }
+
+    //16位基址加变址：先按未进位的地址读一次，跨页时这次读是空读
+    //写和读改写指令无论是否跨页都会先空读
+    fn indexed_address(&mut self, base: u16, index: u8, access: Access) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        self.page_crossed = page_crossed(base, addr);
+        if self.page_crossed || access == Access::Write {
+            self.bus_read((base & 0xff00) | (addr & 0x00ff));
         }
+        addr
     }
 
     pub fn setvaluetoregistera(&mut self, para: u8) {
@@ -262,327 +660,4131 @@ impl CPU {
         self.setstatus(StatusType::NegativeFlag, self.register_y & 0b1000_0000 != 0);
     }
 
-    pub fn adc(&mut self,mode:&AddressingModes){
-        let addr = self.get_operand_address(mode);
-        let mut para = self.read_from_memory_u8(addr);
-        let carrybit = self.getstatus(StatusType::CarryFlag);
-        let signbit = self.getstatus(StatusType::NegativeFlag);
-        if carrybit{
-            para = para.wrapping_add(1);
-        }
-        self.register_a = para;
-        self.setstatus(StatusType::CarryFlag,false);
-
-        self.setstatus(StatusType::ZeroFlag, self.register_a == 0);
-
-        self.setstatus(StatusType::NegativeFlag, self.register_y & 0b1000_0000 != 0);
+    //读指令取操作数，变址跨页时多一次空读
+    fn read_operand(&mut self, mode: &AddressingModes) -> Result<u8, CpuError> {
+        let addr = self.get_operand_address(mode)?;
+        Ok(self.bus_read(addr))
+    }
 
-        if self.getstatus(StatusType::NegativeFlag)!=signbit{
-            self.setstatus(StatusType::OverflowFlag,true);
-            self.setstatus(StatusType::CarryFlag,true);
-        }
+    //根据运算结果设置Z和N标志位
+    fn update_zero_and_negative_flags(&mut self, result: u8) {
+        self.setstatus(StatusType::ZeroFlag, result == 0);
+        self.setstatus(StatusType::NegativeFlag, result & 0b1000_0000 != 0);
     }
 
-    pub fn lda(&mut self, mode: &AddressingModes) {
-        let addr = self.get_operand_address(mode);
-        let para = self.read_from_memory_u8(addr);
-        self.setvaluetoregistera(para)
+    //栈位于0x0100-0x01FF，栈指针向下增长
+    fn stack_push(&mut self, value: u8) {
+        self.bus_write(STACKPOINTERSTART + self.stack_pointer as u16, value);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
-    pub fn ldx(&mut self, mode: &AddressingModes) {
-        let addr = self.get_operand_address(mode);
-        let para = self.read_from_memory_u8(addr);
-        self.setvaluetoregisterx(para)
+    fn stack_pop(&mut self) -> u8 {
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.unwind_call_stack();
+        self.bus_read(STACKPOINTERSTART + self.stack_pointer as u16)
     }
 
-    pub fn ldy(&mut self, mode: &AddressingModes) {
-        let addr = self.get_operand_address(mode);
-        let para = self.read_from_memory_u8(addr);
-        self.setvaluetoregistery(para)
+    //返回地址已经出栈（RTS、PLA丢弃或TXS改写栈指针）的帧不再有效
+    //栈指针在$0100-$01FF内回绕，所以按压入之后栈指针往回移动了多少字节判断：
+    //移动1到depth个字节说明已经出栈，更多是继续压栈回绕的结果
+    //depth是帧到最外层JSR之前栈顶的距离，至少取半页，TXS移到最外层之上时同样出栈
+    fn unwind_call_stack(&mut self) {
+        let Some(base) = self
+            .call_stack
+            .first()
+            .map(|frame| frame.stack_pointer.wrapping_add(2))
+        else {
+            return;
+        };
+        while let Some(frame) = self.call_stack.last() {
+            let depth = base.wrapping_sub(frame.stack_pointer).max(CALLSTACKMINDEPTH);
+            let moved = self.stack_pointer.wrapping_sub(frame.stack_pointer);
+            if moved == 0 || moved > depth {
+                break;
+            }
+            self.call_stack.pop();
+        }
     }
 
-    pub fn sta(&mut self, mode: &AddressingModes) {
-        let addr = self.get_operand_address(mode);
-        self.write_to_memory_u8(addr, self.register_a);
+    //出栈前栈指针加一的那个周期会空读一次当前栈顶
+    fn stack_dummy_read(&mut self) {
+        self.bus_read(STACKPOINTERSTART + self.stack_pointer as u16);
     }
 
-    pub fn stx(&mut self, mode: &AddressingModes) {
-        let addr = self.get_operand_address(mode);
-        self.write_to_memory_u8(addr, self.register_x);
+    //先压高位，再压低位
+    fn stack_push_u16(&mut self, value: u16) {
+        self.stack_push((value >> 8) as u8);
+        self.stack_push((value & 0x00ff) as u8);
     }
 
-    pub fn sty(&mut self, mode: &AddressingModes) {
-        let addr = self.get_operand_address(mode);
-        self.write_to_memory_u8(addr, self.register_y);
+    fn stack_pop_u16(&mut self) -> u16 {
+        let low = self.stack_pop() as u16;
+        let high = self.stack_pop() as u16;
+        (high << 8) | low
     }
 
-    pub fn tax(&mut self) {
-        self.register_x = self.register_a;
-         //判断是否为0
-        self.setstatus(StatusType::ZeroFlag, self.register_x == 0);
-         //判断是否为负
-        self.setstatus(StatusType::NegativeFlag, self.register_x & 0b1000_0000 != 0);
+    //A + M + C，ADC和SBC共用
+    fn add_to_register_a(&mut self, para: u8) {
+        let sum =
+            self.register_a as u16 + para as u16 + self.getstatus(StatusType::CarryFlag) as u16;
+        let result = sum as u8;
+        self.setstatus(StatusType::CarryFlag, sum > 0xff);
+        //两个操作数符号相同，而结果符号不同时溢出
+        self.setstatus(
+            StatusType::OverflowFlag,
+            (para ^ result) & (self.register_a ^ result) & 0b1000_0000 != 0,
+        );
+        self.setvaluetoregistera(result);
     }
 
-    pub fn tay(&mut self) {
-        self.register_y = self.register_a;
-         //判断是否为0
-        self.setstatus(StatusType::ZeroFlag, self.register_y == 0);
-         //判断是否为负
-        self.setstatus(StatusType::NegativeFlag, self.register_y & 0b1000_0000 != 0);
+    //只有NMOS 6502在D标志为1时做十进制运算，2A03忽略D标志
+    fn decimal_mode(&mut self) -> bool {
+        self.variant == Variant::Nmos6502 && self.getstatus(StatusType::DecimalModeFlag)
     }
 
-    pub fn tsx(&mut self) {
-        self.register_x = self.stack_pointer;
-         //判断是否为0
-        self.setstatus(StatusType::ZeroFlag, self.register_x == 0);
-         //判断是否为负
-        self.setstatus(StatusType::NegativeFlag, self.register_x & 0b1000_0000 != 0);
+    //ADC、RRA共用
+    fn add_with_carry(&mut self, para: u8) {
+        if !self.decimal_mode() {
+            self.add_to_register_a(para);
+            return;
+        }
+        //NMOS十进制加法：Z取二进制结果，N和V取高位调整之前的结果
+        let carry = self.getstatus(StatusType::CarryFlag) as u16;
+        let a = self.register_a as u16;
+        let m = para as u16;
+        let mut low = (a & 0x0f) + (m & 0x0f) + carry;
+        if low >= 0x0a {
+            low = ((low + 0x06) & 0x0f) + 0x10;
+        }
+        let mut sum = (a & 0xf0) + (m & 0xf0) + low;
+        self.setstatus(StatusType::ZeroFlag, (a + m + carry) & 0xff == 0);
+        self.setstatus(StatusType::NegativeFlag, sum & 0x80 != 0);
+        self.setstatus(StatusType::OverflowFlag, !(a ^ m) & (a ^ sum) & 0x80 != 0);
+        if sum >= 0xa0 {
+            sum += 0x60;
+        }
+        self.setstatus(StatusType::CarryFlag, sum >= 0x100);
+        self.register_a = sum as u8;
     }
 
-    pub fn txa(&mut self) {
-        self.register_a = self.register_x;
-         //判断是否为0
-        self.setstatus(StatusType::ZeroFlag, self.register_a == 0);
-         //判断是否为负
-        self.setstatus(StatusType::NegativeFlag, self.register_a & 0b1000_0000 != 0);
+    //A - M - (1 - C) 等价于 A + !M + C，SBC、ISB共用
+    fn subtract_with_borrow(&mut self, para: u8) {
+        if !self.decimal_mode() {
+            self.add_to_register_a(!para);
+            return;
+        }
+        //NMOS十进制减法：标志位和二进制减法完全相同，只有结果按BCD调整
+        let a = self.register_a as i16;
+        let m = para as i16;
+        let carry = self.getstatus(StatusType::CarryFlag) as i16;
+        let mut low = (a & 0x0f) - (m & 0x0f) + carry - 1;
+        if low < 0 {
+            low = ((low - 0x06) & 0x0f) - 0x10;
+        }
+        let mut result = (a & 0xf0) - (m & 0xf0) + low;
+        if result < 0 {
+            result -= 0x60;
+        }
+        self.add_to_register_a(!para);
+        self.register_a = result as u8;
     }
 
-    pub fn txs(&mut self) {
-        self.stack_pointer = self.register_x;
+    pub fn adc(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.add_with_carry(para);
+        Ok(())
     }
 
-    pub fn tya(&mut self) {
-        self.register_a = self.register_y;
-         //判断是否为0
-        self.setstatus(StatusType::ZeroFlag, self.register_a == 0);
-         //判断是否为负
-        self.setstatus(StatusType::NegativeFlag, self.register_a & 0b1000_0000 != 0);
+    pub fn sbc(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.subtract_with_borrow(para);
+        Ok(())
     }
 
-    //初始化cpu
-    pub fn load(&mut self, program: Vec<u8>) {
-        //将程序从
-        self.memory[0x8000..(0x8000 + program.len())].copy_from_slice(&program[..]);
-        self.write_to_memory_u16(RESETADDRESS, PROGRAMSTARTADDRESS);
+    pub fn and(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.setvaluetoregistera(self.register_a & para);
+        Ok(())
     }
 
-    pub fn reset(&mut self) {
-        self.register_a = 0;
-        self.register_x = 0;
-        self.register_y = 0;
-        self.status = 0;
-        self.program_counter = self.read_from_memory_u16(RESETADDRESS);
-        self.stack_pointer = STACKRESET;
+    pub fn eor(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.setvaluetoregistera(self.register_a ^ para);
+        Ok(())
     }
 
-    //解析程序指令
-    pub fn run(&mut self) {
-        let ref opcodes = *CPU_OPRAND_HASHMAP;
-        loop {
-            //读取第一个参数
-            let operatecode = self.read_from_memory_u8(self.program_counter);
-            self.program_counter += 1;
-            let program_counter_state = self.program_counter;
-            let opcode = opcodes
-                .get(&operatecode)
-                .expect(&format!("OpCode {:x} is not recognized", operatecode));
-
-            match opcode.opname {
-                "ADC"=>{
-                    self.adc(&opcode.addressmode);
-                }
-                "LDA" => {
-                    //LDA
-                    self.lda(&opcode.addressmode);
-                }
-                "LDX" => {
-                    //LDX
-                    self.ldx(&opcode.addressmode);
-                }
-                "LDY" => {
-                    //LDY
-                    self.ldy(&opcode.addressmode);
-                }
-                "STA" => {
-                    self.sta(&opcode.addressmode);
-                }
-                "STX" => {
-                    self.stx(&opcode.addressmode);
-                }
-                "STY" => {
-                    self.sty(&opcode.addressmode);
-                }
-                "TAX" => {
-                    //TAX
-                    self.tax();
-                }
-                "TAY" => {
-                    //TAY
-                    self.tay();
-                }
-                "TSX" => {
-                    //TAY
-                    self.tsx();
-                }
-                "TXA" => {
-                    //TXA
-                    self.txa();
-                }
-                "TXS" => {
-                    //TAY
-                    self.txs();
-                }
-                "TYA" => {
-                    //TYA
-                    self.tya();
-                }
+    pub fn ora(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.setvaluetoregistera(self.register_a | para);
+        Ok(())
+    }
 
-                
-                "BRK" => {
-                    //BRK
-                    return;
-                }
-                _ => print!("undefined operator!"),
+    //移位指令既可以操作累加器，也可以操作内存
+    fn read_shift_operand(
+        &mut self,
+        mode: &AddressingModes,
+    ) -> Result<(Option<u16>, u8), CpuError> {
+        match mode {
+            AddressingModes::Accumulator => Ok((None, self.register_a)),
+            _ => {
+                let addr = self.get_write_address(mode)?;
+                Ok((Some(addr), self.read_modify(addr)))
             }
+        }
+    }
+
+    //读改写指令先读出原值，再把原值写回一次，最后才写入结果
+    fn read_modify(&mut self, addr: u16) -> u8 {
+        let value = self.bus_read(addr);
+        self.bus_write(addr, value);
+        value
+    }
 
-            if program_counter_state == self.program_counter {
-                self.program_counter += (opcode.bytes - 1) as u16;
+    fn write_shift_result(&mut self, addr: Option<u16>, result: u8) -> u8 {
+        match addr {
+            None => self.setvaluetoregistera(result),
+            Some(addr) => {
+                self.bus_write(addr, result);
+                self.update_zero_and_negative_flags(result);
             }
         }
+        result
     }
 
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
-        self.load(program);
-        self.reset();
-        self.run();
+    pub fn asl(&mut self, mode: &AddressingModes) -> Result<u8, CpuError> {
+        let (addr, para) = self.read_shift_operand(mode)?;
+        self.setstatus(StatusType::CarryFlag, para & 0b1000_0000 != 0);
+        Ok(self.write_shift_result(addr, para << 1))
     }
-}
 
-#[cfg(test)]
+    pub fn lsr(&mut self, mode: &AddressingModes) -> Result<u8, CpuError> {
+        let (addr, para) = self.read_shift_operand(mode)?;
+        self.setstatus(StatusType::CarryFlag, para & 0b0000_0001 != 0);
+        Ok(self.write_shift_result(addr, para >> 1))
+    }
 
-mod tests {
-    use super::*;
+    pub fn rol(&mut self, mode: &AddressingModes) -> Result<u8, CpuError> {
+        let (addr, para) = self.read_shift_operand(mode)?;
+        let carrybit = self.getstatus(StatusType::CarryFlag) as u8;
+        self.setstatus(StatusType::CarryFlag, para & 0b1000_0000 != 0);
+        Ok(self.write_shift_result(addr, (para << 1) | carrybit))
+    }
 
-    #[test]
-    fn setstatusworks() {
-        let value = u16::from_le_bytes([0x34, 0x12]);
-        assert_eq!(value, 0x1234);
+    pub fn ror(&mut self, mode: &AddressingModes) -> Result<u8, CpuError> {
+        let (addr, para) = self.read_shift_operand(mode)?;
+        let carrybit = self.getstatus(StatusType::CarryFlag) as u8;
+        self.setstatus(StatusType::CarryFlag, para & 0b0000_0001 != 0);
+        Ok(self.write_shift_result(addr, (para >> 1) | (carrybit << 7)))
     }
 
-    #[test]
-    fn write_u8_should_work() {
-        let mut ncpu = CPU::new();
-        ncpu.write_to_memory_u8(0x0001, 0x01);
-        assert_eq!(ncpu.read_from_memory_u8(0x0001), 0x01);
+    pub fn inc(&mut self, mode: &AddressingModes) -> Result<u8, CpuError> {
+        let addr = self.get_write_address(mode)?;
+        let result = self.read_modify(addr).wrapping_add(1);
+        self.bus_write(addr, result);
+        self.update_zero_and_negative_flags(result);
+        Ok(result)
     }
 
-    #[test]
-    fn write_u16_should_work() {
-        let mut ncpu = CPU::new();
-        ncpu.write_to_memory_u16(0x0021, 0x0001);
-        assert_eq!(ncpu.read_from_memory_u16(0x0021), 0x0001);
+    pub fn dec(&mut self, mode: &AddressingModes) -> Result<u8, CpuError> {
+        let addr = self.get_write_address(mode)?;
+        let result = self.read_modify(addr).wrapping_sub(1);
+        self.bus_write(addr, result);
+        self.update_zero_and_negative_flags(result);
+        Ok(result)
     }
 
-    #[test]
-    fn setstatus_c_should_work() {
-        let mut ncpu = CPU::new();
-        ncpu.setstatus(StatusType::CarryFlag, true);
-        assert_eq!(ncpu.status, 0b0000_0001);
-        ncpu.setstatus(StatusType::CarryFlag, false);
-        assert_eq!(ncpu.status, 0b0000_0000);
-        ncpu.setstatus(StatusType::CarryFlag, true);
-        assert_eq!(ncpu.getstatus(StatusType::CarryFlag), true);
+    pub fn inx(&mut self) {
+        self.setvaluetoregisterx(self.register_x.wrapping_add(1));
     }
 
-    #[test]
-    fn setstatus_z_should_work() {
-        let mut ncpu = CPU::new();
-        ncpu.setstatus(StatusType::ZeroFlag, true);
-        assert_eq!(ncpu.status, 0b0000_0010);
-        ncpu.setstatus(StatusType::ZeroFlag, false);
-        assert_eq!(ncpu.status, 0b0000_0000);
+    pub fn iny(&mut self) {
+        self.setvaluetoregistery(self.register_y.wrapping_add(1));
     }
 
-    #[test]
-    fn setstatus_i_should_work() {
-        let mut ncpu = CPU::new();
-        ncpu.setstatus(StatusType::InterruptDisable, true);
-        assert_eq!(ncpu.status, 0b0000_0100);
-        ncpu.setstatus(StatusType::InterruptDisable, false);
-        assert_eq!(ncpu.status, 0b0000_0000);
+    pub fn dex(&mut self) {
+        self.setvaluetoregisterx(self.register_x.wrapping_sub(1));
     }
 
-    #[test]
-    fn setstatus_d_should_work() {
-        let mut ncpu = CPU::new();
-        ncpu.setstatus(StatusType::DecimalModeFlag, true);
-        assert_eq!(ncpu.status, 0b0000_1000);
-        ncpu.setstatus(StatusType::DecimalModeFlag, false);
-        assert_eq!(ncpu.status, 0b0000_0000);
+    pub fn dey(&mut self) {
+        self.setvaluetoregistery(self.register_y.wrapping_sub(1));
     }
 
-    #[test]
-    fn setstatus_b_should_work() {
-        let mut ncpu = CPU::new();
-        ncpu.setstatus(StatusType::Break, true);
-        assert_eq!(ncpu.status, 0b0001_0000);
-        ncpu.setstatus(StatusType::Break, false);
-        assert_eq!(ncpu.status, 0b0000_0000);
+    //比较指令：C = 寄存器 >= M，Z和N取决于 寄存器 - M
+    fn compare(&mut self, mode: &AddressingModes, register: u8) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.compare_value(register, para);
+        Ok(())
     }
 
-    #[test]
-    fn setstatus_v_should_work() {
-        let mut ncpu = CPU::new();
-        ncpu.setstatus(StatusType::OverflowFlag, true);
-        assert_eq!(ncpu.status, 0b0100_0000);
-        ncpu.setstatus(StatusType::OverflowFlag, false);
-        assert_eq!(ncpu.status, 0b0000_0000);
+    fn compare_value(&mut self, register: u8, para: u8) {
+        self.setstatus(StatusType::CarryFlag, register >= para);
+        self.update_zero_and_negative_flags(register.wrapping_sub(para));
     }
 
-    #[test]
-    fn setstatus_n_should_work() {
-        let mut ncpu = CPU::new();
-        ncpu.setstatus(StatusType::NegativeFlag, true);
-        assert_eq!(ncpu.status, 0b1000_0000);
-        ncpu.setstatus(StatusType::NegativeFlag, false);
-        assert_eq!(ncpu.status, 0b0000_0000);
+    pub fn cmp(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        self.compare(mode, self.register_a)?;
+        Ok(())
     }
-    #[test]
-    fn set_data_to_rigestera_should_work() {
-        let mut ncpu = CPU::new();
+
+    pub fn cpx(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        self.compare(mode, self.register_x)?;
+        Ok(())
+    }
+
+    pub fn cpy(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        self.compare(mode, self.register_y)?;
+        Ok(())
+    }
+
+    //Z = A & M == 0，V和N直接取M的第6、7位
+    pub fn bit(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.setstatus(StatusType::ZeroFlag, self.register_a & para == 0);
+        self.setstatus(StatusType::OverflowFlag, para & 0b0100_0000 != 0);
+        self.setstatus(StatusType::NegativeFlag, para & 0b1000_0000 != 0);
+        Ok(())
+    }
+
+    //标志位等于expected时跳转，偏移量是有符号数，相对于下一条指令的地址
+    //跳转时空读一次下一条指令，跳到另一页时再按未进位的地址空读一次
+    fn branch(
+        &mut self,
+        mode: &AddressingModes,
+        flag: StatusType,
+        expected: bool,
+    ) -> Result<(), CpuError> {
+        let target = self.get_operand_address(mode)?;
+        if self.getstatus(flag) == expected {
+            let next = self.program_counter;
+            self.bus_read(next);
+            if page_crossed(next, target) {
+                self.bus_read((next & 0xff00) | (target & 0x00ff));
+            }
+            self.program_counter = target;
+        }
+        Ok(())
+    }
+
+    pub fn jmp(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        self.program_counter = self.get_operand_address(mode)?;
+        Ok(())
+    }
+
+    //JSR压入的是返回地址减一，即本条指令的最后一个字节
+    //硬件先取低字节，压栈之后才取高字节
+    pub fn jsr(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        if !matches!(mode, AddressingModes::Absolute) {
+            return Err(CpuError::InvalidAddressingMode(*mode));
+        }
+        let low = self.fetch() as u16;
+        self.stack_dummy_read();
+        self.stack_push_u16(self.program_counter);
+        let high = self.bus_read(self.program_counter) as u16;
+        let return_address = self.program_counter.wrapping_add(1);
+        self.program_counter = (high << 8) | low;
+        //没有配对RTS的JSR（递归、PLA丢弃返回地址后再跳转）会让栈回绕，丢弃最早的帧
+        if self.call_stack.len() == CALLSTACKMAXFRAMES {
+            self.call_stack.remove(0);
+        }
+        self.call_stack.push(CallFrame {
+            caller: return_address.wrapping_sub(3),
+            target: self.program_counter,
+            return_address,
+            stack_pointer: self.stack_pointer,
+        });
+        Ok(())
+    }
+
+    //出栈后空读一次返回地址，再加一
+    pub fn rts(&mut self) {
+        self.stack_dummy_read();
+        self.program_counter = self.stack_pop_u16();
+        self.bus_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+    }
+
+    pub fn rti(&mut self) {
+        self.stack_dummy_read();
+        self.pull_status();
+        self.program_counter = self.stack_pop_u16();
+    }
+
+    pub fn pha(&mut self) {
+        self.stack_push(self.register_a);
+    }
+
+    pub fn pla(&mut self) {
+        self.stack_dummy_read();
+        let para = self.stack_pop();
+        self.setvaluetoregistera(para);
+    }
+
+    //PHP压栈时B和第5位都置1
+    pub fn php(&mut self) {
+        self.stack_push(self.status.pushed(true));
+    }
+
+    pub fn plp(&mut self) {
+        self.stack_dummy_read();
+        self.pull_status();
+    }
+
+    //PLP/RTI出栈时忽略B和第5位
+    fn pull_status(&mut self) {
+        self.status = Status::from_bits(self.stack_pop());
+    }
+
+    pub fn lda(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.setvaluetoregistera(para);
+        Ok(())
+    }
+
+    pub fn ldx(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.setvaluetoregisterx(para);
+        Ok(())
+    }
+
+    pub fn ldy(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.setvaluetoregistery(para);
+        Ok(())
+    }
+
+    pub fn sta(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let addr = self.get_write_address(mode)?;
+        self.bus_write(addr, self.register_a);
+        Ok(())
+    }
+
+    pub fn stx(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let addr = self.get_write_address(mode)?;
+        self.bus_write(addr, self.register_x);
+        Ok(())
+    }
+
+    pub fn sty(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let addr = self.get_write_address(mode)?;
+        self.bus_write(addr, self.register_y);
+        Ok(())
+    }
+
+    pub fn tax(&mut self) {
+        self.register_x = self.register_a;
+        //判断是否为0
+        self.setstatus(StatusType::ZeroFlag, self.register_x == 0);
+        //判断是否为负
+        self.setstatus(StatusType::NegativeFlag, self.register_x & 0b1000_0000 != 0);
+    }
+
+    pub fn tay(&mut self) {
+        self.register_y = self.register_a;
+        //判断是否为0
+        self.setstatus(StatusType::ZeroFlag, self.register_y == 0);
+        //判断是否为负
+        self.setstatus(StatusType::NegativeFlag, self.register_y & 0b1000_0000 != 0);
+    }
+
+    pub fn tsx(&mut self) {
+        self.register_x = self.stack_pointer;
+        //判断是否为0
+        self.setstatus(StatusType::ZeroFlag, self.register_x == 0);
+        //判断是否为负
+        self.setstatus(StatusType::NegativeFlag, self.register_x & 0b1000_0000 != 0);
+    }
+
+    pub fn txa(&mut self) {
+        self.register_a = self.register_x;
+        //判断是否为0
+        self.setstatus(StatusType::ZeroFlag, self.register_a == 0);
+        //判断是否为负
+        self.setstatus(StatusType::NegativeFlag, self.register_a & 0b1000_0000 != 0);
+    }
+
+    pub fn txs(&mut self) {
+        self.stack_pointer = self.register_x;
+        self.unwind_call_stack();
+    }
+
+    pub fn tya(&mut self) {
+        self.register_a = self.register_y;
+        //判断是否为0
+        self.setstatus(StatusType::ZeroFlag, self.register_a == 0);
+        //判断是否为负
+        self.setstatus(StatusType::NegativeFlag, self.register_a & 0b1000_0000 != 0);
+    }
+
+    //NMI是边沿触发的，只有从无效变为有效时才会产生一次中断
+    //总线的NMI（例如PPU）和这里设置的输入线是线或的关系
+    pub fn set_nmi_line(&mut self, asserted: bool) {
+        self.nmi_line = asserted;
+        self.resample_interrupts();
+    }
+
+    //IRQ是电平触发的，只要保持有效且I标志为0就会不断产生中断
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+        self.resample_interrupts();
+    }
+
+    //在两条指令之间改变输入线，视为在上一条指令的轮询点之前就已经改变
+    fn resample_interrupts(&mut self) {
+        self.interrupt_sampled = self.interrupt_requested();
+        self.interrupt_polled = self.interrupt_sampled;
+    }
+
+    //检测NMI边沿，返回是否需要响应中断
+    fn interrupt_requested(&mut self) -> bool {
+        let nmi = self.nmi_line || self.memory.nmi();
+        if nmi && !self.nmi_input {
+            self.nmi_pending = true;
+        }
+        self.nmi_input = nmi;
+        let irq = self.irq_line || self.memory.irq();
+        self.nmi_pending || (irq && !self.getstatus(StatusType::InterruptDisable))
+    }
+
+    //硬件在指令倒数第二个周期轮询中断，I标志也按那时的值计算：
+    //CLI、SEI、PLP在最后一个周期才改变I，要再执行一条指令才生效；
+    //RTI在倒数第三个周期出栈状态，下一次轮询就能看到新的I
+    fn sample_interrupts(&mut self) {
+        self.interrupt_polled = self.interrupt_sampled;
+        self.interrupt_sampled = self.interrupt_requested();
+    }
+
+    //OAM DMA：先暂停一个周期，写$4014之后是奇数周期时再多等一个周期对齐，
+    //然后256次读$XX00-$XXFF、写$2004交替进行，共513或514个周期
+    fn oam_dma(&mut self, page: u8) {
+        let odd = self.cycles % 2 == 1;
+        self.bus_read(self.program_counter);
+        if odd {
+            self.bus_read(self.program_counter);
+        }
+        let start = (page as u16) << 8;
+        for offset in 0..OAMDMABYTES {
+            let value = self.bus_read(start + offset);
+            self.bus_write(OAMDATA, value);
+        }
+    }
+
+    //硬件中断：空读两次PC，压入PC和状态（B为0），置I，读中断向量，共7个周期
+    fn interrupt(&mut self) -> Interrupt {
+        self.bus_read(self.program_counter);
+        self.bus_read(self.program_counter);
+        self.stack_push_u16(self.program_counter);
+        self.stack_push(self.status.pushed(false));
+        self.setstatus(StatusType::InterruptDisable, true);
+        let (interrupt, vector) = self.interrupt_vector();
+        self.program_counter = self.read_vector(vector);
+        interrupt
+    }
+
+    //压入状态之后才选择向量，在此之前到来的NMI会劫持IRQ和BRK
+    fn interrupt_vector(&mut self) -> (Interrupt, u16) {
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            (Interrupt::Nmi, NMIADDRESS)
+        } else {
+            (Interrupt::Irq, IRQADDRESS)
+        }
+    }
+
+    fn read_vector(&mut self, vector: u16) -> u16 {
+        let low = self.bus_read(vector) as u16;
+        let high = self.bus_read(vector.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+
+    //上一条指令轮询到中断时，先响应中断，NMI优先于IRQ
+    fn poll_interrupts(&mut self) -> Option<Interrupt> {
+        if !self.interrupt_polled {
+            return None;
+        }
+        self.interrupt_polled = false;
+        Some(self.interrupt())
+    }
+
+    //BRK后面有一个填充字节，压入的返回地址是BRK地址+2，状态中B为1
+    //如果此时有NMI等待响应，BRK会被NMI劫持，跳到NMI向量，但压入的B仍为1
+    pub fn brk(&mut self) {
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
+        self.stack_push(self.status.pushed(true));
+        self.setstatus(StatusType::InterruptDisable, true);
+        let (_, vector) = self.interrupt_vector();
+        self.program_counter = self.read_vector(vector);
+        //和硬件中断一样，结束时不轮询，处理程序的第一条指令总会执行
+        self.interrupt_polled = false;
+    }
+
+    //非官方指令
+
+    //带寻址方式的NOP同样会读内存，abs,X跨页时多一个周期
+    pub fn nop(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        if !matches!(mode, AddressingModes::NoAddressingMode) {
+            self.read_operand(mode)?;
+        }
+        Ok(())
+    }
+
+    //LDA + LDX
+    pub fn lax(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.setvaluetoregistera(para);
+        self.register_x = para;
+        Ok(())
+    }
+
+    //M = A & X，不影响标志位
+    pub fn sax(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let addr = self.get_write_address(mode)?;
+        self.bus_write(addr, self.register_a & self.register_x);
+        Ok(())
+    }
+
+    //DEC + CMP
+    pub fn dcp(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let result = self.dec(mode)?;
+        self.compare_value(self.register_a, result);
+        Ok(())
+    }
+
+    //INC + SBC
+    pub fn isb(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let result = self.inc(mode)?;
+        self.subtract_with_borrow(result);
+        Ok(())
+    }
+
+    //ASL + ORA
+    pub fn slo(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let result = self.asl(mode)?;
+        self.setvaluetoregistera(self.register_a | result);
+        Ok(())
+    }
+
+    //ROL + AND
+    pub fn rla(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let result = self.rol(mode)?;
+        self.setvaluetoregistera(self.register_a & result);
+        Ok(())
+    }
+
+    //LSR + EOR
+    pub fn sre(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let result = self.lsr(mode)?;
+        self.setvaluetoregistera(self.register_a ^ result);
+        Ok(())
+    }
+
+    //ROR + ADC
+    pub fn rra(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let result = self.ror(mode)?;
+        self.add_with_carry(result);
+        Ok(())
+    }
+
+    //AND，然后C = N
+    pub fn anc(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        self.and(mode)?;
+        let negative = self.getstatus(StatusType::NegativeFlag);
+        self.setstatus(StatusType::CarryFlag, negative);
+        Ok(())
+    }
+
+    //AND + LSR A
+    pub fn alr(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        self.and(mode)?;
+        self.lsr(&AddressingModes::Accumulator)?;
+        Ok(())
+    }
+
+    //AND + ROR A，C取结果第6位，V = 第6位 ^ 第5位
+    pub fn arr(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        self.and(mode)?;
+        let result = self.ror(&AddressingModes::Accumulator)?;
+        self.setstatus(StatusType::CarryFlag, result & 0b0100_0000 != 0);
+        self.setstatus(
+            StatusType::OverflowFlag,
+            ((result >> 6) ^ (result >> 5)) & 0b0000_0001 != 0,
+        );
+        Ok(())
+    }
+
+    //X = (A & X) - M，与CMP一样设置C，不受借位影响
+    pub fn axs(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        let value = self.register_a & self.register_x;
+        self.compare_value(value, para);
+        self.register_x = value.wrapping_sub(para);
+        Ok(())
+    }
+
+    //不稳定：A = (A | 0xEE) & X & M
+    pub fn xaa(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.setvaluetoregistera((self.register_a | UNSTABLEMAGIC) & self.register_x & para);
+        Ok(())
+    }
+
+    //不稳定：A = X = (A | 0xEE) & M
+    pub fn lxa(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        self.setvaluetoregistera((self.register_a | UNSTABLEMAGIC) & para);
+        self.register_x = self.register_a;
+        Ok(())
+    }
+
+    //AHX/SHX/SHY/TAS写入 value & (基址高字节 + 1)，跨页时写入值同时替换地址高字节
+    fn unstable_store(&mut self, mode: &AddressingModes, value: u8) -> Result<(), CpuError> {
+        let addr = self.get_write_address(mode)?;
+        let high = (addr >> 8) as u8;
+        let (value, addr) = if self.page_crossed {
+            let value = value & high;
+            (value, ((value as u16) << 8) | (addr & 0x00ff))
+        } else {
+            (value & high.wrapping_add(1), addr)
+        };
+        self.bus_write(addr, value);
+        Ok(())
+    }
+
+    //SP = A & X，再按AHX的方式写入 SP & (H + 1)
+    pub fn tas(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        self.stack_pointer = self.register_a & self.register_x;
+        self.unstable_store(mode, self.stack_pointer)?;
+        Ok(())
+    }
+
+    //A = X = SP = M & SP
+    pub fn las(&mut self, mode: &AddressingModes) -> Result<(), CpuError> {
+        let para = self.read_operand(mode)?;
+        let value = para & self.stack_pointer;
+        self.setvaluetoregistera(value);
+        self.register_x = value;
+        self.stack_pointer = value;
+        Ok(())
+    }
+
+    //CPU停机，PC停在KIL指令处，只有复位能恢复
+    pub fn kil(&mut self) {
+        self.jammed = true;
+        self.program_counter = self.program_counter.wrapping_sub(1);
+    }
+
+    pub fn jammed(&self) -> bool {
+        self.jammed
+    }
+
+    //初始化cpu
+    pub fn load(&mut self, program: Vec<u8>) -> Result<(), CpuError> {
+        let start = PROGRAMSTARTADDRESS as usize;
+        let max = MEMORYSIZE - start;
+        if program.len() > max {
+            return Err(CpuError::ProgramTooLarge {
+                size: program.len(),
+                max,
+            });
+        }
+        //将程序从0x8000开始写入，写完读回检查，ROM会忽略写入
+        for (offset, value) in program.iter().enumerate() {
+            let add = PROGRAMSTARTADDRESS + offset as u16;
+            self.write_to_memory_u8(add, *value);
+            if self.read_from_memory_u8(add) != *value {
+                return Err(CpuError::ReadOnlyMemory(add));
+            }
+        }
+        //程序覆盖了中断向量时使用程序自己的向量
+        if program.len() <= (RESETADDRESS - PROGRAMSTARTADDRESS) as usize {
+            self.write_to_memory_u16(RESETADDRESS, PROGRAMSTARTADDRESS);
+            if self.read_from_memory_u16(RESETADDRESS) != PROGRAMSTARTADDRESS {
+                return Err(CpuError::ReadOnlyMemory(RESETADDRESS));
+            }
+        }
+        Ok(())
+    }
+
+    //上电：寄存器清零，I置位，栈指针从0经过复位序列变为$FD
+    pub fn power_on(&mut self) {
+        self.memory.power_on();
+        self.register_a = 0;
+        self.register_x = 0;
+        self.register_y = 0;
+        self.status = Status::default();
+        self.stack_pointer = STACKRESET.wrapping_add(RESETSTACKDECREMENT);
+        self.cycles = 0;
+        self.nmi_line = false;
+        self.nmi_input = false;
+        self.irq_line = false;
+        self.reset();
+    }
+
+    //复位：A/X/Y和RAM保持不变，I置位，栈指针减3，从复位向量开始执行
+    //复位序列和中断一样占用7个周期：空读两次PC，三次压栈变成读栈，最后读复位向量
+    pub fn reset(&mut self) {
+        self.memory.reset();
+        self.call_stack.clear();
+        self.jammed = false;
+        self.nmi_pending = false;
+        self.interrupt_sampled = false;
+        self.interrupt_polled = false;
+        self.bus_read(self.program_counter);
+        self.bus_read(self.program_counter);
+        for _ in 0..RESETSTACKDECREMENT {
+            self.stack_dummy_read();
+            self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        }
+        self.setstatus(StatusType::InterruptDisable, true);
+        self.program_counter = self.read_vector(RESETADDRESS);
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    //从指定地址开始执行，例如nestest的自动模式从$C000开始
+    pub fn set_program_counter(&mut self, program_counter: u16) {
+        self.program_counter = program_counter;
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            register_a: self.register_a,
+            register_x: self.register_x,
+            register_y: self.register_y,
+            status: self.status.bits(),
+            stack_pointer: self.stack_pointer,
+            program_counter: self.program_counter,
+        }
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.register_a = registers.register_a;
+        self.register_x = registers.register_x;
+        self.register_y = registers.register_y;
+        self.status = Status::from_bits(registers.status);
+        self.stack_pointer = registers.stack_pointer;
+        self.program_counter = registers.program_counter;
+        self.unwind_call_stack();
+    }
+
+    //当前调用栈，最外层在前，最内层（正在执行的子程序）在后
+    pub fn call_stack(&self) -> &[CallFrame] {
+        &self.call_stack
+    }
+
+    fn jammed_error(&self) -> CpuError {
+        CpuError::Jammed {
+            opcode: self.read_from_memory_u8(self.program_counter),
+            address: self.program_counter,
+        }
+    }
+
+    //开启跟踪，之后每条指令执行前向writer写一行日志
+    pub fn set_tracer<W: Write + 'static>(&mut self, writer: W) {
+        self.tracer = Some(Box::new(writer));
+    }
+
+    //关闭跟踪，返回原来的writer
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Write>> {
+        self.tracer.take()
+    }
+
+    fn write_trace(&mut self) -> Result<(), CpuError> {
+        let line = self.trace();
+        if let Some(tracer) = self.tracer.as_mut() {
+            writeln!(tracer, "{}", line).map_err(|error| CpuError::TraceFailed(error.kind()))?;
+        }
+        Ok(())
+    }
+
+    //nestest.log格式的一行：PC、指令字节、反汇编、寄存器、PPU位置和周期数
+    //C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+    //只用peek读内存，不会产生副作用
+    pub fn trace(&self) -> String {
+        let pc = self.program_counter;
+        let code = self.read_from_memory_u8(pc);
+        let (bytes, mark, text) = match INSTRUCTION_TABLE[code as usize] {
+            Some(instruction) => {
+                let opcode = instruction.opcode;
+                let bytes: Vec<String> = (0..opcode.bytes as u16)
+                    .map(|i| format!("{:02X}", self.read_from_memory_u8(pc.wrapping_add(i))))
+                    .collect();
+                let mark = if opcode.unofficial { '*' } else { ' ' };
+                let text = format!("{} {}", opcode.opname, self.trace_operand(opcode));
+                (bytes.join(" "), mark, text)
+            }
+            None => (format!("{:02X}", code), ' ', String::from("???")),
+        };
+        //总线上有PPU时使用它的位置，否则按NTSC每个CPU周期3个点从周期数推算
+        let (scanline, dot) = self.memory.ppu_position().unwrap_or_else(|| {
+            let dots = self.cycles * PPUDOTSPERCYCLE;
+            (
+                ((dots / PPUDOTSPERSCANLINE) % PPUSCANLINESPERFRAME) as u16,
+                (dots % PPUDOTSPERSCANLINE) as u16,
+            )
+        });
+        format!(
+            "{:04X}  {:<9}{}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            pc,
+            bytes,
+            mark,
+            text.trim_end(),
+            self.register_a,
+            self.register_x,
+            self.register_y,
+            self.status.bits(),
+            self.stack_pointer,
+            scanline,
+            dot,
+            self.cycles
+        )
+    }
+
+    //按nestest的写法显示操作数，以及有效地址和其中的值
+    fn trace_operand(&self, opcode: &OpCode) -> String {
+        let pc = self.program_counter;
+        let para = self.read_from_memory_u8(pc.wrapping_add(1));
+        let para16 = self.read_from_memory_u16(pc.wrapping_add(1));
+        let peek = |add: u16| self.read_from_memory_u8(add);
+        let peek_zero_page = |pointer: u8| {
+            let low = peek(pointer as u16) as u16;
+            let high = peek(pointer.wrapping_add(1) as u16) as u16;
+            (high << 8) | low
+        };
+        match opcode.addressmode {
+            AddressingModes::NoAddressingMode => String::new(),
+            AddressingModes::Accumulator => String::from("A"),
+            AddressingModes::Immediate => format!("#${:02X}", para),
+            AddressingModes::ZeroPage => format!("${:02X} = {:02X}", para, peek(para as u16)),
+            AddressingModes::ZeroPageX => {
+                let addr = para.wrapping_add(self.register_x);
+                format!("${:02X},X @ {:02X} = {:02X}", para, addr, peek(addr as u16))
+            }
+            AddressingModes::ZeroPageY => {
+                let addr = para.wrapping_add(self.register_y);
+                format!("${:02X},Y @ {:02X} = {:02X}", para, addr, peek(addr as u16))
+            }
+            AddressingModes::Relative => {
+                let target = pc.wrapping_add(2).wrapping_add(para as i8 as u16);
+                format!("${:04X}", target)
+            }
+            AddressingModes::Absolute => {
+                if matches!(opcode.opname, "JMP" | "JSR") {
+                    format!("${:04X}", para16)
+                } else {
+                    format!("${:04X} = {:02X}", para16, peek(para16))
+                }
+            }
+            AddressingModes::AbsoluteX => {
+                let addr = para16.wrapping_add(self.register_x as u16);
+                format!("${:04X},X @ {:04X} = {:02X}", para16, addr, peek(addr))
+            }
+            AddressingModes::AbsoluteY => {
+                let addr = para16.wrapping_add(self.register_y as u16);
+                format!("${:04X},Y @ {:04X} = {:02X}", para16, addr, peek(addr))
+            }
+            AddressingModes::Indirect => {
+                let high_addr = (para16 & 0xff00) | (para16.wrapping_add(1) & 0x00ff);
+                let target = ((peek(high_addr) as u16) << 8) | peek(para16) as u16;
+                format!("(${:04X}) = {:04X}", para16, target)
+            }
+            AddressingModes::IndexedIndirect => {
+                let pointer = para.wrapping_add(self.register_x);
+                let addr = peek_zero_page(pointer);
+                format!(
+                    "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                    para,
+                    pointer,
+                    addr,
+                    peek(addr)
+                )
+            }
+            AddressingModes::IndirectIndexed => {
+                let base = peek_zero_page(para);
+                let addr = base.wrapping_add(self.register_y as u16);
+                format!(
+                    "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                    para,
+                    base,
+                    addr,
+                    peek(addr)
+                )
+            }
+        }
+    }
+
+    //按分派表中的操作执行指令，隐寻址的指令不需要计算地址，不会出错
+    fn execute(&mut self, instruction: Instruction) -> Result<(), CpuError> {
+        let mode = &instruction.opcode.addressmode;
+        match instruction.operation {
+            Operation::Adc => self.adc(mode),
+            Operation::And => self.and(mode),
+            Operation::Asl => self.asl(mode).map(|_| ()),
+            Operation::Bcc => self.branch(mode, StatusType::CarryFlag, false),
+            Operation::Bcs => self.branch(mode, StatusType::CarryFlag, true),
+            Operation::Beq => self.branch(mode, StatusType::ZeroFlag, true),
+            Operation::Bit => self.bit(mode),
+            Operation::Bmi => self.branch(mode, StatusType::NegativeFlag, true),
+            Operation::Bne => self.branch(mode, StatusType::ZeroFlag, false),
+            Operation::Bpl => self.branch(mode, StatusType::NegativeFlag, false),
+            Operation::Brk => {
+                self.brk();
+                Ok(())
+            }
+            Operation::Bvc => self.branch(mode, StatusType::OverflowFlag, false),
+            Operation::Bvs => self.branch(mode, StatusType::OverflowFlag, true),
+            Operation::Clc => {
+                self.setstatus(StatusType::CarryFlag, false);
+                Ok(())
+            }
+            Operation::Cld => {
+                self.setstatus(StatusType::DecimalModeFlag, false);
+                Ok(())
+            }
+            Operation::Cli => {
+                self.setstatus(StatusType::InterruptDisable, false);
+                Ok(())
+            }
+            Operation::Clv => {
+                self.setstatus(StatusType::OverflowFlag, false);
+                Ok(())
+            }
+            Operation::Cmp => self.cmp(mode),
+            Operation::Cpx => self.cpx(mode),
+            Operation::Cpy => self.cpy(mode),
+            Operation::Dec => self.dec(mode).map(|_| ()),
+            Operation::Dex => {
+                self.dex();
+                Ok(())
+            }
+            Operation::Dey => {
+                self.dey();
+                Ok(())
+            }
+            Operation::Eor => self.eor(mode),
+            Operation::Inc => self.inc(mode).map(|_| ()),
+            Operation::Inx => {
+                self.inx();
+                Ok(())
+            }
+            Operation::Iny => {
+                self.iny();
+                Ok(())
+            }
+            Operation::Jmp => self.jmp(mode),
+            Operation::Jsr => self.jsr(mode),
+            Operation::Lda => self.lda(mode),
+            Operation::Ldx => self.ldx(mode),
+            Operation::Ldy => self.ldy(mode),
+            Operation::Lsr => self.lsr(mode).map(|_| ()),
+            Operation::Nop => self.nop(mode),
+            Operation::Ora => self.ora(mode),
+            Operation::Pha => {
+                self.pha();
+                Ok(())
+            }
+            Operation::Php => {
+                self.php();
+                Ok(())
+            }
+            Operation::Pla => {
+                self.pla();
+                Ok(())
+            }
+            Operation::Plp => {
+                self.plp();
+                Ok(())
+            }
+            Operation::Rol => self.rol(mode).map(|_| ()),
+            Operation::Ror => self.ror(mode).map(|_| ()),
+            Operation::Rti => {
+                self.rti();
+                Ok(())
+            }
+            Operation::Rts => {
+                self.rts();
+                Ok(())
+            }
+            Operation::Sbc => self.sbc(mode),
+            Operation::Sec => {
+                self.setstatus(StatusType::CarryFlag, true);
+                Ok(())
+            }
+            Operation::Sed => {
+                self.setstatus(StatusType::DecimalModeFlag, true);
+                Ok(())
+            }
+            Operation::Sei => {
+                self.setstatus(StatusType::InterruptDisable, true);
+                Ok(())
+            }
+            Operation::Sta => self.sta(mode),
+            Operation::Stx => self.stx(mode),
+            Operation::Sty => self.sty(mode),
+            Operation::Tax => {
+                self.tax();
+                Ok(())
+            }
+            Operation::Tay => {
+                self.tay();
+                Ok(())
+            }
+            Operation::Tsx => {
+                self.tsx();
+                Ok(())
+            }
+            Operation::Txa => {
+                self.txa();
+                Ok(())
+            }
+            Operation::Txs => {
+                self.txs();
+                Ok(())
+            }
+            Operation::Tya => {
+                self.tya();
+                Ok(())
+            }
+            Operation::Lax => self.lax(mode),
+            Operation::Sax => self.sax(mode),
+            Operation::Dcp => self.dcp(mode),
+            Operation::Isb => self.isb(mode),
+            Operation::Slo => self.slo(mode),
+            Operation::Rla => self.rla(mode),
+            Operation::Sre => self.sre(mode),
+            Operation::Rra => self.rra(mode),
+            Operation::Anc => self.anc(mode),
+            Operation::Alr => self.alr(mode),
+            Operation::Arr => self.arr(mode),
+            Operation::Axs => self.axs(mode),
+            Operation::Xaa => self.xaa(mode),
+            Operation::Lxa => self.lxa(mode),
+            Operation::Ahx => self.unstable_store(mode, self.register_a & self.register_x),
+            Operation::Shx => self.unstable_store(mode, self.register_x),
+            Operation::Shy => self.unstable_store(mode, self.register_y),
+            Operation::Tas => self.tas(mode),
+            Operation::Las => self.las(mode),
+            Operation::Kil => {
+                self.kil();
+                Ok(())
+            }
+        }
+    }
+
+    //执行一条指令（如果有中断，先响应中断），逐周期完成指令的每一次总线读写
+    //执行KIL的这一步返回Halted，之后再调用返回CpuError::Jammed
+    pub fn step(&mut self) -> Result<StepResult, CpuError> {
+        let start_cycles = self.cycles;
+        if self.jammed {
+            return Err(self.jammed_error());
+        }
+        let interrupt = self.poll_interrupts();
+        if self.tracer.is_some() {
+            self.write_trace()?;
+        }
+
+        //读取第一个参数
+        let address = self.program_counter;
+        let operatecode = self.fetch();
+        let instruction =
+            INSTRUCTION_TABLE[operatecode as usize].ok_or(CpuError::UnknownOpcode {
+                opcode: operatecode,
+                address,
+            })?;
+
+        //单字节指令在第二个周期空读一次下一个字节
+        if matches!(
+            instruction.opcode.addressmode,
+            AddressingModes::NoAddressingMode | AddressingModes::Accumulator
+        ) {
+            self.bus_read(self.program_counter);
+        }
+        //每次总线访问计一个周期，取操作数的过程中PC移到下一条指令
+        self.execute(instruction)?;
+        //写$4014的指令结束后CPU暂停，由DMA占用总线
+        if let Some(page) = self.memory.take_dma() {
+            self.oam_dma(page);
+        }
+
+        let event = if self.jammed {
+            StepEvent::Halted
+        } else if operatecode == 0x00 {
+            StepEvent::Break
+        } else if self.program_counter == address {
+            StepEvent::Trapped
+        } else {
+            StepEvent::Normal
+        };
+        Ok(StepResult {
+            address,
+            opcode: operatecode,
+            cycles: self.cycles - start_cycles,
+            interrupt,
+            event,
+        })
+    }
+
+    //解析程序指令，执行到BRK时返回，CPU停机时返回CpuError::Jammed
+    pub fn run(&mut self) -> Result<(), CpuError> {
+        self.run_until(|_, result| result.event == StepEvent::Break)
+            .map(|_| ())
+    }
+
+    //至少执行n个周期，返回实际执行的周期数
+    pub fn run_for_cycles(&mut self, n: u64) -> Result<u64, CpuError> {
+        let start_cycles = self.cycles;
+        while self.cycles - start_cycles < n {
+            if self.step()?.event == StepEvent::Halted {
+                return Err(self.jammed_error());
+            }
+        }
+        Ok(self.cycles - start_cycles)
+    }
+
+    //逐条执行直到predicate返回true，返回最后一步的结果
+    pub fn run_until<F>(&mut self, mut predicate: F) -> Result<StepResult, CpuError>
+    where
+        F: FnMut(&CPU<M>, &StepResult) -> bool,
+    {
+        loop {
+            let result = self.step()?;
+            if result.event == StepEvent::Halted {
+                return Err(self.jammed_error());
+            }
+            if predicate(self, &result) {
+                return Ok(result);
+            }
+        }
+    }
+
+    pub fn load_and_run(&mut self, program: Vec<u8>) -> Result<(), CpuError> {
+        self.load(program)?;
+        self.power_on();
+        self.run()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::bus::Bus;
+    use crate::cpuoperand::CPU_OPRAND;
+
+    const RESETCYCLES: u64 = 7;
+
+    //加载程序并复位，执行setup后运行到BRK
+    fn run_program<F: FnOnce(&mut CPU)>(program: Vec<u8>, setup: F) -> CPU {
+        let mut ncpu = CPU::new();
+        ncpu.load(program).unwrap();
+        ncpu.power_on();
+        setup(&mut ncpu);
+        ncpu.run().unwrap();
+        ncpu
+    }
+
+    //汇编后从0x8000开始运行
+    fn run_assembly<F: FnOnce(&mut CPU)>(source: &str, setup: F) -> CPU {
+        run_program(assemble(source, PROGRAMSTARTADDRESS).unwrap(), setup)
+    }
+
+    //run在执行BRK后返回，BRK压入的返回地址减2即为BRK所在的地址
+    fn brk_address(ncpu: &CPU) -> u16 {
+        let top = STACKPOINTERSTART + ncpu.stack_pointer as u16;
+        ncpu.read_from_memory_u16(top + 2) - 2
+    }
+
+    //BRK压入的状态，即BRK执行前的标志位
+    fn brk_pushed_status(ncpu: &CPU) -> u8 {
+        ncpu.read_from_memory_u8(STACKPOINTERSTART + ncpu.stack_pointer as u16 + 1)
+    }
+
+    #[test]
+    fn setstatusworks() {
+        let value = u16::from_le_bytes([0x34, 0x12]);
+        assert_eq!(value, 0x1234);
+    }
+
+    #[test]
+    fn write_u8_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.write_to_memory_u8(0x0001, 0x01);
+        assert_eq!(ncpu.read_from_memory_u8(0x0001), 0x01);
+    }
+
+    #[test]
+    fn write_u16_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.write_to_memory_u16(0x0021, 0x0001);
+        assert_eq!(ncpu.read_from_memory_u16(0x0021), 0x0001);
+    }
+
+    #[test]
+    fn setstatus_c_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.setstatus(StatusType::CarryFlag, true);
+        assert_eq!(ncpu.status, Status::from_bits(0b0000_0001));
+        ncpu.setstatus(StatusType::CarryFlag, false);
+        assert_eq!(ncpu.status, Status::from_bits(0b0000_0000));
+        ncpu.setstatus(StatusType::CarryFlag, true);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn setstatus_z_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.setstatus(StatusType::ZeroFlag, true);
+        assert_eq!(ncpu.status, Status::from_bits(0b0000_0010));
+        ncpu.setstatus(StatusType::ZeroFlag, false);
+        assert_eq!(ncpu.status, Status::from_bits(0b0000_0000));
+    }
+
+    #[test]
+    fn setstatus_i_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.setstatus(StatusType::InterruptDisable, true);
+        assert_eq!(ncpu.status, Status::from_bits(0b0000_0100));
+        ncpu.setstatus(StatusType::InterruptDisable, false);
+        assert_eq!(ncpu.status, Status::from_bits(0b0000_0000));
+    }
+
+    #[test]
+    fn setstatus_d_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.setstatus(StatusType::DecimalModeFlag, true);
+        assert_eq!(ncpu.status, Status::from_bits(0b0000_1000));
+        ncpu.setstatus(StatusType::DecimalModeFlag, false);
+        assert_eq!(ncpu.status, Status::from_bits(0b0000_0000));
+    }
+
+    #[test]
+    fn status_should_not_store_break_bits() {
+        let status = Status::from_bits(0xff);
+        assert_eq!(status.bits(), 0b1110_1111);
+        assert_eq!(status.pushed(true), 0xff);
+        assert_eq!(status.pushed(false), 0b1110_1111);
+    }
+
+    #[test]
+    fn setstatus_v_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.setstatus(StatusType::OverflowFlag, true);
+        assert_eq!(ncpu.status, Status::from_bits(0b0100_0000));
+        ncpu.setstatus(StatusType::OverflowFlag, false);
+        assert_eq!(ncpu.status, Status::from_bits(0b0000_0000));
+    }
+
+    #[test]
+    fn setstatus_n_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.setstatus(StatusType::NegativeFlag, true);
+        assert_eq!(ncpu.status, Status::from_bits(0b1000_0000));
+        ncpu.setstatus(StatusType::NegativeFlag, false);
+        assert_eq!(ncpu.status, Status::from_bits(0b0000_0000));
+    }
+    #[test]
+    fn set_data_to_rigestera_should_work() {
+        let mut ncpu = CPU::new();
         ncpu.setvaluetoregistera(0x55);
         assert_eq!(ncpu.register_a, 0x55);
     }
 
     #[test]
-    fn reset_should_work() {
+    fn instruction_table_should_work() {
+        for opcode in CPU_OPRAND.iter() {
+            let instruction = INSTRUCTION_TABLE[opcode.opcode as usize].unwrap();
+            assert_eq!(instruction.opcode.opcode, opcode.opcode);
+            assert_eq!(instruction.opcode.opname, opcode.opname);
+        }
+        assert_eq!(CPU_OPRAND.len(), 256);
+        assert!(INSTRUCTION_TABLE.iter().all(|i| i.is_some()));
+    }
+
+    #[test]
+    fn reset_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.load(vec![0xa5, 0x10, 0x00]).unwrap();
+        ncpu.power_on();
+        assert_eq!(ncpu.program_counter, 0x8000);
+        let operatecode = ncpu.read_from_memory_u8(ncpu.program_counter);
+        assert_eq!(operatecode, 0xa5);
+        assert_eq!(ncpu.stack_pointer, STACKRESET);
+        assert_eq!(ncpu.status.bits(), 0b0010_0100);
+    }
+
+    #[test]
+    fn reset_should_keep_registers_and_decrement_stack_pointer() {
+        let mut ncpu = load_program(vec![0xa9, 0x55, 0xa2, 0x66, 0x00]);
+        ncpu.run().unwrap();
+        ncpu.write_to_memory_u8(0x0010, 0x77);
+        ncpu.setstatus(StatusType::InterruptDisable, false);
+        let stack_pointer = ncpu.stack_pointer;
+        let cycles = ncpu.cycles();
+        ncpu.reset();
+        assert_eq!(ncpu.program_counter, 0x8000);
+        assert_eq!(ncpu.register_a, 0x55);
+        assert_eq!(ncpu.register_x, 0x66);
+        assert_eq!(ncpu.stack_pointer, stack_pointer.wrapping_sub(3));
+        assert!(ncpu.getstatus(StatusType::InterruptDisable));
+        assert_eq!(ncpu.read_from_memory_u8(0x0010), 0x77);
+        assert_eq!(ncpu.cycles(), cycles + RESETCYCLES);
+    }
+
+    #[test]
+    fn lda_from_memory_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.write_to_memory_u8(0x10, 0x55);
+
+        ncpu.load_and_run(vec![0xa5, 0x10, 0x00]).unwrap();
+
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn sta_should_work() {
+        let mut ncpu = CPU::new();
+        // ncpu.write_to_memory_u8(0x11, 0x10);
+        ncpu.load(vec![0x85, 0x10, 0x00]).unwrap();
+        ncpu.power_on();
+        ncpu.register_a = 0x55;
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.read_from_memory_u8(0x10), 0x55);
+    }
+
+    #[test]
+    fn adc_immediate_should_work() {
+        let ncpu = run_program(vec![0x69, 0x10, 0x00], |ncpu| ncpu.register_a = 0x20);
+        assert_eq!(ncpu.register_a, 0x30);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn adc_zeropage_should_work() {
+        let ncpu = run_program(vec![0x65, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.write_to_memory_u8(0x0010, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn adc_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0x75, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn adc_absolute_should_work() {
+        let ncpu = run_program(vec![0x6d, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.write_to_memory_u8(0x0200, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn adc_absolute_x_should_work() {
+        let ncpu = run_program(vec![0x7d, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn adc_absolute_y_should_work() {
+        let ncpu = run_program(vec![0x79, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn adc_indexed_indirect_should_work() {
+        let ncpu = run_program(vec![0x61, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u16(0x11, 0x0200);
+            ncpu.write_to_memory_u8(0x0200, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn adc_indirect_indexed_should_work() {
+        let ncpu = run_program(vec![0x71, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u16(0x10, 0x0200);
+            ncpu.write_to_memory_u8(0x0201, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn and_immediate_should_work() {
+        let ncpu = run_program(vec![0x29, 0x3c, 0x00], |ncpu| ncpu.register_a = 0xf0);
+        assert_eq!(ncpu.register_a, 0x30);
+    }
+
+    #[test]
+    fn and_zeropage_should_work() {
+        let ncpu = run_program(vec![0x25, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.write_to_memory_u8(0x0010, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+    }
+
+    #[test]
+    fn and_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0x35, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+    }
+
+    #[test]
+    fn and_absolute_should_work() {
+        let ncpu = run_program(vec![0x2d, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.write_to_memory_u8(0x0200, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+    }
+
+    #[test]
+    fn and_absolute_x_should_work() {
+        let ncpu = run_program(vec![0x3d, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+    }
+
+    #[test]
+    fn and_absolute_y_should_work() {
+        let ncpu = run_program(vec![0x39, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+    }
+
+    #[test]
+    fn and_indexed_indirect_should_work() {
+        let ncpu = run_program(vec![0x21, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u16(0x11, 0x0200);
+            ncpu.write_to_memory_u8(0x0200, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+    }
+
+    #[test]
+    fn and_indirect_indexed_should_work() {
+        let ncpu = run_program(vec![0x31, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u16(0x10, 0x0200);
+            ncpu.write_to_memory_u8(0x0201, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0x30);
+    }
+
+    #[test]
+    fn cmp_immediate_should_work() {
+        let ncpu = run_program(vec![0xc9, 0x10, 0x00], |ncpu| ncpu.register_a = 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(!ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn cmp_zeropage_should_work() {
+        let ncpu = run_program(vec![0xc5, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.write_to_memory_u8(0x0010, 0x10);
+        });
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(!ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn cmp_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0xd5, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x10);
+        });
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(!ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn cmp_absolute_should_work() {
+        let ncpu = run_program(vec![0xcd, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.write_to_memory_u8(0x0200, 0x10);
+        });
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(!ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn cmp_absolute_x_should_work() {
+        let ncpu = run_program(vec![0xdd, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x10);
+        });
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(!ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn cmp_absolute_y_should_work() {
+        let ncpu = run_program(vec![0xd9, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x10);
+        });
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(!ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn cmp_indexed_indirect_should_work() {
+        let ncpu = run_program(vec![0xc1, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u16(0x11, 0x0200);
+            ncpu.write_to_memory_u8(0x0200, 0x10);
+        });
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(!ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn cmp_indirect_indexed_should_work() {
+        let ncpu = run_program(vec![0xd1, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u16(0x10, 0x0200);
+            ncpu.write_to_memory_u8(0x0201, 0x10);
+        });
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(!ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn eor_immediate_should_work() {
+        let ncpu = run_program(vec![0x49, 0x3c, 0x00], |ncpu| ncpu.register_a = 0xf0);
+        assert_eq!(ncpu.register_a, 0xcc);
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn eor_zeropage_should_work() {
+        let ncpu = run_program(vec![0x45, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.write_to_memory_u8(0x0010, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0xcc);
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn eor_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0x55, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0xcc);
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn eor_absolute_should_work() {
+        let ncpu = run_program(vec![0x4d, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.write_to_memory_u8(0x0200, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0xcc);
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn eor_absolute_x_should_work() {
+        let ncpu = run_program(vec![0x5d, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0xcc);
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn eor_absolute_y_should_work() {
+        let ncpu = run_program(vec![0x59, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0xcc);
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn eor_indexed_indirect_should_work() {
+        let ncpu = run_program(vec![0x41, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u16(0x11, 0x0200);
+            ncpu.write_to_memory_u8(0x0200, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0xcc);
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn eor_indirect_indexed_should_work() {
+        let ncpu = run_program(vec![0x51, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u16(0x10, 0x0200);
+            ncpu.write_to_memory_u8(0x0201, 0x3c);
+        });
+        assert_eq!(ncpu.register_a, 0xcc);
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn lda_immediate_should_work() {
+        let ncpu = run_program(vec![0xa9, 0x55, 0x00], |_| {});
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn lda_zeropage_should_work() {
+        let ncpu = run_program(vec![0xa5, 0x10, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0010, 0x55)
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn lda_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0xb5, 0x10, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x55);
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn lda_absolute_should_work() {
+        let ncpu = run_program(vec![0xad, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0200, 0x55)
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn lda_absolute_x_should_work() {
+        let ncpu = run_program(vec![0xbd, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x55);
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn lda_absolute_y_should_work() {
+        let ncpu = run_program(vec![0xb9, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x55);
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn lda_indexed_indirect_should_work() {
+        let ncpu = run_program(vec![0xa1, 0x10, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u16(0x11, 0x0200);
+            ncpu.write_to_memory_u8(0x0200, 0x55);
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn lda_indirect_indexed_should_work() {
+        let ncpu = run_program(vec![0xb1, 0x10, 0x00], |ncpu| {
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u16(0x10, 0x0200);
+            ncpu.write_to_memory_u8(0x0201, 0x55);
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn ora_immediate_should_work() {
+        let ncpu = run_program(vec![0x09, 0x0f, 0x00], |ncpu| ncpu.register_a = 0xf0);
+        assert_eq!(ncpu.register_a, 0xff);
+    }
+
+    #[test]
+    fn ora_zeropage_should_work() {
+        let ncpu = run_program(vec![0x05, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.write_to_memory_u8(0x0010, 0x0f);
+        });
+        assert_eq!(ncpu.register_a, 0xff);
+    }
+
+    #[test]
+    fn ora_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0x15, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x0f);
+        });
+        assert_eq!(ncpu.register_a, 0xff);
+    }
+
+    #[test]
+    fn ora_absolute_should_work() {
+        let ncpu = run_program(vec![0x0d, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.write_to_memory_u8(0x0200, 0x0f);
+        });
+        assert_eq!(ncpu.register_a, 0xff);
+    }
+
+    #[test]
+    fn ora_absolute_x_should_work() {
+        let ncpu = run_program(vec![0x1d, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x0f);
+        });
+        assert_eq!(ncpu.register_a, 0xff);
+    }
+
+    #[test]
+    fn ora_absolute_y_should_work() {
+        let ncpu = run_program(vec![0x19, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x0f);
+        });
+        assert_eq!(ncpu.register_a, 0xff);
+    }
+
+    #[test]
+    fn ora_indexed_indirect_should_work() {
+        let ncpu = run_program(vec![0x01, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u16(0x11, 0x0200);
+            ncpu.write_to_memory_u8(0x0200, 0x0f);
+        });
+        assert_eq!(ncpu.register_a, 0xff);
+    }
+
+    #[test]
+    fn ora_indirect_indexed_should_work() {
+        let ncpu = run_program(vec![0x11, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u16(0x10, 0x0200);
+            ncpu.write_to_memory_u8(0x0201, 0x0f);
+        });
+        assert_eq!(ncpu.register_a, 0xff);
+    }
+
+    #[test]
+    fn sbc_immediate_should_work() {
+        let ncpu = run_program(vec![0xe9, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x30;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+        });
+        assert_eq!(ncpu.register_a, 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn sbc_zeropage_should_work() {
+        let ncpu = run_program(vec![0xe5, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x30;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.write_to_memory_u8(0x0010, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn sbc_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0xf5, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x30;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn sbc_absolute_should_work() {
+        let ncpu = run_program(vec![0xed, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x30;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.write_to_memory_u8(0x0200, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn sbc_absolute_x_should_work() {
+        let ncpu = run_program(vec![0xfd, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x30;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn sbc_absolute_y_should_work() {
+        let ncpu = run_program(vec![0xf9, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x30;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn sbc_indexed_indirect_should_work() {
+        let ncpu = run_program(vec![0xe1, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x30;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u16(0x11, 0x0200);
+            ncpu.write_to_memory_u8(0x0200, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn sbc_indirect_indexed_should_work() {
+        let ncpu = run_program(vec![0xf1, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x30;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u16(0x10, 0x0200);
+            ncpu.write_to_memory_u8(0x0201, 0x10);
+        });
+        assert_eq!(ncpu.register_a, 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn bit_zeropage_should_work() {
+        let ncpu = run_program(vec![0x24, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x01;
+            ncpu.write_to_memory_u8(0x0010, 0xc0);
+        });
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+        assert!(ncpu.getstatus(StatusType::OverflowFlag));
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn bit_absolute_should_work() {
+        let ncpu = run_program(vec![0x2c, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x01;
+            ncpu.write_to_memory_u8(0x0200, 0xc0);
+        });
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+        assert!(ncpu.getstatus(StatusType::OverflowFlag));
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn cpx_immediate_should_work() {
+        let ncpu = run_program(vec![0xe0, 0x20, 0x00], |ncpu| ncpu.register_x = 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn cpx_zeropage_should_work() {
+        let ncpu = run_program(vec![0xe4, 0x10, 0x00], |ncpu| {
+            ncpu.register_x = 0x20;
+            ncpu.write_to_memory_u8(0x0010, 0x20);
+        });
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn cpx_absolute_should_work() {
+        let ncpu = run_program(vec![0xec, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_x = 0x20;
+            ncpu.write_to_memory_u8(0x0200, 0x20);
+        });
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn cpy_immediate_should_work() {
+        let ncpu = run_program(vec![0xc0, 0x20, 0x00], |ncpu| ncpu.register_y = 0x10);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn cpy_zeropage_should_work() {
+        let ncpu = run_program(vec![0xc4, 0x10, 0x00], |ncpu| {
+            ncpu.register_y = 0x10;
+            ncpu.write_to_memory_u8(0x0010, 0x20);
+        });
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn cpy_absolute_should_work() {
+        let ncpu = run_program(vec![0xcc, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_y = 0x10;
+            ncpu.write_to_memory_u8(0x0200, 0x20);
+        });
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn ldx_immediate_should_work() {
+        let ncpu = run_program(vec![0xa2, 0x55, 0x00], |_| {});
+        assert_eq!(ncpu.register_x, 0x55);
+    }
+
+    #[test]
+    fn ldx_zeropage_should_work() {
+        let ncpu = run_program(vec![0xa6, 0x10, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0010, 0x55)
+        });
+        assert_eq!(ncpu.register_x, 0x55);
+    }
+
+    #[test]
+    fn ldx_zeropage_y_should_work() {
+        let ncpu = run_program(vec![0xb6, 0x10, 0x00], |ncpu| {
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x55);
+        });
+        assert_eq!(ncpu.register_x, 0x55);
+    }
+
+    #[test]
+    fn ldx_absolute_should_work() {
+        let ncpu = run_program(vec![0xae, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0200, 0x55)
+        });
+        assert_eq!(ncpu.register_x, 0x55);
+    }
+
+    #[test]
+    fn ldx_absolute_y_should_work() {
+        let ncpu = run_program(vec![0xbe, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x55);
+        });
+        assert_eq!(ncpu.register_x, 0x55);
+    }
+
+    #[test]
+    fn ldy_immediate_should_work() {
+        let ncpu = run_program(vec![0xa0, 0x55, 0x00], |_| {});
+        assert_eq!(ncpu.register_y, 0x55);
+    }
+
+    #[test]
+    fn ldy_zeropage_should_work() {
+        let ncpu = run_program(vec![0xa4, 0x10, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0010, 0x55)
+        });
+        assert_eq!(ncpu.register_y, 0x55);
+    }
+
+    #[test]
+    fn ldy_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0xb4, 0x10, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x55);
+        });
+        assert_eq!(ncpu.register_y, 0x55);
+    }
+
+    #[test]
+    fn ldy_absolute_should_work() {
+        let ncpu = run_program(vec![0xac, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0200, 0x55)
+        });
+        assert_eq!(ncpu.register_y, 0x55);
+    }
+
+    #[test]
+    fn ldy_absolute_x_should_work() {
+        let ncpu = run_program(vec![0xbc, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x55);
+        });
+        assert_eq!(ncpu.register_y, 0x55);
+    }
+
+    #[test]
+    fn sta_zeropage_should_work() {
+        let ncpu = run_program(vec![0x85, 0x10, 0x00], |ncpu| ncpu.register_a = 0x55);
+        assert_eq!(ncpu.read_from_memory_u8(0x0010), 0x55);
+    }
+
+    #[test]
+    fn sta_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0x95, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x55;
+            ncpu.register_x = 0x01;
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0011), 0x55);
+    }
+
+    #[test]
+    fn sta_absolute_should_work() {
+        let ncpu = run_program(vec![0x8d, 0x00, 0x02, 0x00], |ncpu| ncpu.register_a = 0x55);
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x55);
+    }
+
+    #[test]
+    fn sta_absolute_x_should_work() {
+        let ncpu = run_program(vec![0x9d, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x55;
+            ncpu.register_x = 0x01;
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0201), 0x55);
+    }
+
+    #[test]
+    fn sta_absolute_y_should_work() {
+        let ncpu = run_program(vec![0x99, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x55;
+            ncpu.register_y = 0x01;
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0201), 0x55);
+    }
+
+    #[test]
+    fn sta_indexed_indirect_should_work() {
+        let ncpu = run_program(vec![0x81, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x55;
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u16(0x11, 0x0200);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x55);
+    }
+
+    #[test]
+    fn sta_indirect_indexed_should_work() {
+        let ncpu = run_program(vec![0x91, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x55;
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u16(0x10, 0x0200);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0201), 0x55);
+    }
+
+    #[test]
+    fn stx_zeropage_should_work() {
+        let ncpu = run_program(vec![0x86, 0x10, 0x00], |ncpu| ncpu.register_x = 0x55);
+        assert_eq!(ncpu.read_from_memory_u8(0x0010), 0x55);
+    }
+
+    #[test]
+    fn stx_zeropage_y_should_work() {
+        let ncpu = run_program(vec![0x96, 0x10, 0x00], |ncpu| {
+            ncpu.register_x = 0x55;
+            ncpu.register_y = 0x01;
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0011), 0x55);
+    }
+
+    #[test]
+    fn stx_absolute_should_work() {
+        let ncpu = run_program(vec![0x8e, 0x00, 0x02, 0x00], |ncpu| ncpu.register_x = 0x55);
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x55);
+    }
+
+    #[test]
+    fn sty_zeropage_should_work() {
+        let ncpu = run_program(vec![0x84, 0x10, 0x00], |ncpu| ncpu.register_y = 0x55);
+        assert_eq!(ncpu.read_from_memory_u8(0x0010), 0x55);
+    }
+
+    #[test]
+    fn sty_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0x94, 0x10, 0x00], |ncpu| {
+            ncpu.register_y = 0x55;
+            ncpu.register_x = 0x01;
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0011), 0x55);
+    }
+
+    #[test]
+    fn sty_absolute_should_work() {
+        let ncpu = run_program(vec![0x8c, 0x00, 0x02, 0x00], |ncpu| ncpu.register_y = 0x55);
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x55);
+    }
+
+    #[test]
+    fn asl_accumulator_should_work() {
+        let ncpu = run_program(vec![0x0a, 0x00], |ncpu| ncpu.register_a = 0x81);
+        assert_eq!(ncpu.register_a, 0x02);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn asl_zeropage_should_work() {
+        let ncpu = run_program(vec![0x06, 0x10, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0010, 0x81)
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0010), 0x02);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn asl_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0x16, 0x10, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x81);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0011), 0x02);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn asl_absolute_should_work() {
+        let ncpu = run_program(vec![0x0e, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0200, 0x81)
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x02);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn asl_absolute_x_should_work() {
+        let ncpu = run_program(vec![0x1e, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x81);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0201), 0x02);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn lsr_accumulator_should_work() {
+        let ncpu = run_program(vec![0x4a, 0x00], |ncpu| ncpu.register_a = 0x81);
+        assert_eq!(ncpu.register_a, 0x40);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn lsr_zeropage_should_work() {
+        let ncpu = run_program(vec![0x46, 0x10, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0010, 0x81)
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0010), 0x40);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn lsr_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0x56, 0x10, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x81);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0011), 0x40);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn lsr_absolute_should_work() {
+        let ncpu = run_program(vec![0x4e, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0200, 0x81)
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x40);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn lsr_absolute_x_should_work() {
+        let ncpu = run_program(vec![0x5e, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x81);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0201), 0x40);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn rol_accumulator_should_work() {
+        let ncpu = run_program(vec![0x2a, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.register_a = 0x81;
+        });
+        assert_eq!(ncpu.register_a, 0x03);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn rol_zeropage_should_work() {
+        let ncpu = run_program(vec![0x26, 0x10, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.write_to_memory_u8(0x0010, 0x81);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0010), 0x03);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn rol_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0x36, 0x10, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x81);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0011), 0x03);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn rol_absolute_should_work() {
+        let ncpu = run_program(vec![0x2e, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.write_to_memory_u8(0x0200, 0x81);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x03);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn rol_absolute_x_should_work() {
+        let ncpu = run_program(vec![0x3e, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x81);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0201), 0x03);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn ror_accumulator_should_work() {
+        let ncpu = run_program(vec![0x6a, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.register_a = 0x80;
+        });
+        assert_eq!(ncpu.register_a, 0xc0);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn ror_zeropage_should_work() {
+        let ncpu = run_program(vec![0x66, 0x10, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.write_to_memory_u8(0x0010, 0x80);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0010), 0xc0);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn ror_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0x76, 0x10, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x80);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0011), 0xc0);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn ror_absolute_should_work() {
+        let ncpu = run_program(vec![0x6e, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.write_to_memory_u8(0x0200, 0x80);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0xc0);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn ror_absolute_x_should_work() {
+        let ncpu = run_program(vec![0x7e, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x80);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0201), 0xc0);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn inc_zeropage_should_work() {
+        let ncpu = run_program(vec![0xe6, 0x10, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0010, 0x10)
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0010), 0x11);
+    }
+
+    #[test]
+    fn inc_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0xf6, 0x10, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x10);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0011), 0x11);
+    }
+
+    #[test]
+    fn inc_absolute_should_work() {
+        let ncpu = run_program(vec![0xee, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0200, 0x10)
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x11);
+    }
+
+    #[test]
+    fn inc_absolute_x_should_work() {
+        let ncpu = run_program(vec![0xfe, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x10);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0201), 0x11);
+    }
+
+    #[test]
+    fn dec_zeropage_should_work() {
+        let ncpu = run_program(vec![0xc6, 0x10, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0010, 0x10)
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0010), 0x0f);
+    }
+
+    #[test]
+    fn dec_zeropage_x_should_work() {
+        let ncpu = run_program(vec![0xd6, 0x10, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0011, 0x10);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0011), 0x0f);
+    }
+
+    #[test]
+    fn dec_absolute_should_work() {
+        let ncpu = run_program(vec![0xce, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0200, 0x10)
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x0f);
+    }
+
+    #[test]
+    fn dec_absolute_x_should_work() {
+        let ncpu = run_program(vec![0xde, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x0201, 0x10);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0201), 0x0f);
+    }
+
+    #[test]
+    fn adc_with_carry_in_should_work() {
+        let ncpu = run_program(vec![0x69, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+        });
+        assert_eq!(ncpu.register_a, 0x31);
+    }
+
+    #[test]
+    fn adc_carry_out_should_work() {
+        let ncpu = run_program(vec![0x69, 0x01, 0x00], |ncpu| ncpu.register_a = 0xff);
+        assert_eq!(ncpu.register_a, 0x00);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+        assert!(!ncpu.getstatus(StatusType::OverflowFlag));
+    }
+
+    #[test]
+    fn adc_overflow_should_work() {
+        let ncpu = run_program(vec![0x69, 0x50, 0x00], |ncpu| ncpu.register_a = 0x50);
+        assert_eq!(ncpu.register_a, 0xa0);
+        assert!(ncpu.getstatus(StatusType::OverflowFlag));
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn sbc_borrow_should_work() {
+        let ncpu = run_program(vec![0xe9, 0x01, 0x00], |ncpu| {
+            ncpu.register_a = 0x00;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+        });
+        assert_eq!(ncpu.register_a, 0xff);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn sbc_overflow_should_work() {
+        let ncpu = run_program(vec![0xe9, 0x01, 0x00], |ncpu| {
+            ncpu.register_a = 0x80;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+        });
+        assert_eq!(ncpu.register_a, 0x7f);
+        assert!(ncpu.getstatus(StatusType::OverflowFlag));
+    }
+
+    #[test]
+    fn cmp_equal_should_work() {
+        let ncpu = run_program(vec![0xc9, 0x20, 0x00], |ncpu| ncpu.register_a = 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn lsr_to_zero_should_work() {
+        let ncpu = run_program(vec![0x4a, 0x00], |ncpu| ncpu.register_a = 0x01);
+        assert_eq!(ncpu.register_a, 0x00);
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn inc_wraps_should_work() {
+        let ncpu = run_program(vec![0xe6, 0x10, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x10, 0xff);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x10), 0x00);
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn inx_should_work() {
+        let ncpu = run_program(vec![0xe8, 0x00], |ncpu| ncpu.register_x = 0xff);
+        assert_eq!(ncpu.register_x, 0x00);
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn iny_should_work() {
+        let ncpu = run_program(vec![0xc8, 0x00], |ncpu| ncpu.register_y = 0x10);
+        assert_eq!(ncpu.register_y, 0x11);
+    }
+
+    #[test]
+    fn dex_should_work() {
+        let ncpu = run_program(vec![0xca, 0x00], |_| {});
+        assert_eq!(ncpu.register_x, 0xff);
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn dey_should_work() {
+        let ncpu = run_program(vec![0x88, 0x00], |ncpu| ncpu.register_y = 0x01);
+        assert_eq!(ncpu.register_y, 0x00);
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn tax_should_work() {
+        let ncpu = run_program(vec![0xaa, 0x00], |ncpu| ncpu.register_a = 0x55);
+        assert_eq!(ncpu.register_x, 0x55);
+    }
+
+    #[test]
+    fn tay_should_work() {
+        let ncpu = run_program(vec![0xa8, 0x00], |ncpu| ncpu.register_a = 0x55);
+        assert_eq!(ncpu.register_y, 0x55);
+    }
+
+    #[test]
+    fn tsx_should_work() {
+        let ncpu = run_program(vec![0xba, 0x00], |_| {});
+        assert_eq!(ncpu.register_x, STACKRESET);
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn txa_should_work() {
+        let ncpu = run_program(vec![0x8a, 0x00], |ncpu| ncpu.register_x = 0x55);
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn txs_should_work() {
+        let ncpu = run_program(vec![0x9a, 0x00], |ncpu| ncpu.register_x = 0x80);
+        assert_eq!(ncpu.stack_pointer, 0x80 - 3);
+    }
+
+    #[test]
+    fn tya_should_work() {
+        let ncpu = run_program(vec![0x98, 0x00], |ncpu| ncpu.register_y = 0x55);
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn clc_should_work() {
+        let ncpu = run_program(vec![0x18, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+        });
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn cld_should_work() {
+        let ncpu = run_program(vec![0xd8, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::DecimalModeFlag, true);
+        });
+        assert!(!ncpu.getstatus(StatusType::DecimalModeFlag));
+    }
+
+    #[test]
+    fn cli_should_work() {
+        let ncpu = run_program(vec![0x58, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::InterruptDisable, true);
+        });
+        assert_eq!(brk_pushed_status(&ncpu) & 0b0000_0100, 0);
+    }
+
+    #[test]
+    fn clv_should_work() {
+        let ncpu = run_program(vec![0xb8, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::OverflowFlag, true);
+        });
+        assert!(!ncpu.getstatus(StatusType::OverflowFlag));
+    }
+
+    #[test]
+    fn sec_should_work() {
+        let ncpu = run_program(vec![0x38, 0x00], |_| {});
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn sed_should_work() {
+        let ncpu = run_program(vec![0xf8, 0x00], |_| {});
+        assert!(ncpu.getstatus(StatusType::DecimalModeFlag));
+    }
+
+    #[test]
+    fn sei_should_work() {
+        let ncpu = run_program(vec![0x78, 0x00], |_| {});
+        assert!(ncpu.getstatus(StatusType::InterruptDisable));
+    }
+
+    #[test]
+    fn nop_should_work() {
+        let ncpu = run_program(vec![0xea, 0x00], |_| {});
+        assert_eq!(brk_address(&ncpu), 0x8001);
+        assert_eq!(ncpu.register_a, 0x00);
+    }
+
+    //条件满足时跳过中间的BRK，执行INX
+    fn run_branch(opcode: u8, flag: StatusType, value: bool) -> CPU {
+        run_program(vec![opcode, 0x02, 0x00, 0x00, 0xe8, 0x00], |ncpu| {
+            ncpu.setstatus(flag, value)
+        })
+    }
+
+    #[test]
+    fn bcc_should_work() {
+        assert_eq!(run_branch(0x90, StatusType::CarryFlag, false).register_x, 1);
+        assert_eq!(run_branch(0x90, StatusType::CarryFlag, true).register_x, 0);
+    }
+
+    #[test]
+    fn bcs_should_work() {
+        assert_eq!(run_branch(0xb0, StatusType::CarryFlag, true).register_x, 1);
+        assert_eq!(run_branch(0xb0, StatusType::CarryFlag, false).register_x, 0);
+    }
+
+    #[test]
+    fn beq_should_work() {
+        assert_eq!(run_branch(0xf0, StatusType::ZeroFlag, true).register_x, 1);
+        assert_eq!(run_branch(0xf0, StatusType::ZeroFlag, false).register_x, 0);
+    }
+
+    #[test]
+    fn bmi_should_work() {
+        assert_eq!(
+            run_branch(0x30, StatusType::NegativeFlag, true).register_x,
+            1
+        );
+        assert_eq!(
+            run_branch(0x30, StatusType::NegativeFlag, false).register_x,
+            0
+        );
+    }
+
+    #[test]
+    fn bne_should_work() {
+        assert_eq!(run_branch(0xd0, StatusType::ZeroFlag, false).register_x, 1);
+        assert_eq!(run_branch(0xd0, StatusType::ZeroFlag, true).register_x, 0);
+    }
+
+    #[test]
+    fn bpl_should_work() {
+        assert_eq!(
+            run_branch(0x10, StatusType::NegativeFlag, false).register_x,
+            1
+        );
+        assert_eq!(
+            run_branch(0x10, StatusType::NegativeFlag, true).register_x,
+            0
+        );
+    }
+
+    #[test]
+    fn bvc_should_work() {
+        assert_eq!(
+            run_branch(0x50, StatusType::OverflowFlag, false).register_x,
+            1
+        );
+        assert_eq!(
+            run_branch(0x50, StatusType::OverflowFlag, true).register_x,
+            0
+        );
+    }
+
+    #[test]
+    fn bvs_should_work() {
+        assert_eq!(
+            run_branch(0x70, StatusType::OverflowFlag, true).register_x,
+            1
+        );
+        assert_eq!(
+            run_branch(0x70, StatusType::OverflowFlag, false).register_x,
+            0
+        );
+    }
+
+    #[test]
+    fn branch_backward_should_work() {
+        //LDX #$03; DEX; BNE -3; BRK
+        let ncpu = run_program(vec![0xa2, 0x03, 0xca, 0xd0, 0xfd, 0x00], |_| {});
+        assert_eq!(ncpu.register_x, 0x00);
+        assert_eq!(brk_address(&ncpu), 0x8005);
+    }
+
+    #[test]
+    fn jmp_absolute_should_work() {
+        let ncpu = run_program(vec![0x4c, 0x05, 0x80, 0x00, 0x00, 0xe8, 0x00], |_| {});
+        assert_eq!(ncpu.register_x, 0x01);
+    }
+
+    #[test]
+    fn jmp_indirect_should_work() {
+        let ncpu = run_program(vec![0x6c, 0x00, 0x02, 0x00, 0x00, 0xe8, 0x00], |ncpu| {
+            ncpu.write_to_memory_u16(0x0200, 0x8005);
+        });
+        assert_eq!(ncpu.register_x, 0x01);
+    }
+
+    #[test]
+    fn jsr_rts_should_work() {
+        //JSR $8005; INX; BRK; INY; RTS
+        let ncpu = run_program(vec![0x20, 0x05, 0x80, 0xe8, 0x00, 0xc8, 0x60], |_| {});
+        assert_eq!(ncpu.register_x, 0x01);
+        assert_eq!(ncpu.register_y, 0x01);
+        assert_eq!(ncpu.stack_pointer, STACKRESET - 3);
+    }
+
+    #[test]
+    fn jsr_pushes_return_address_minus_one() {
+        let ncpu = run_program(vec![0x20, 0x05, 0x80, 0x00, 0x00, 0x00], |_| {});
+        assert_eq!(brk_address(&ncpu), 0x8005);
+        assert_eq!(ncpu.stack_pointer, STACKRESET - 2 - 3);
+        assert_eq!(ncpu.read_from_memory_u16(0x01fc), 0x8002);
+    }
+
+    #[test]
+    fn call_stack_should_follow_jsr_and_rts() {
+        //JSR $8006; BRK; ...; $8006: JSR $800A; RTS; $800A: BRK
+        let program = vec![
+            0x20, 0x06, 0x80, 0x00, 0x00, 0x00, 0x20, 0x0a, 0x80, 0x60, 0x00,
+        ];
+        let ncpu = run_program(program.clone(), |_| {});
+        let frames: Vec<(u16, u16, u16)> = ncpu
+            .call_stack()
+            .iter()
+            .map(|frame| (frame.caller, frame.target, frame.return_address))
+            .collect();
+        assert_eq!(
+            frames,
+            vec![(0x8000, 0x8006, 0x8003), (0x8006, 0x800a, 0x8009)]
+        );
+
+        //$800A改成RTS，两层都返回后调用栈为空
+        let mut program = program;
+        program[10] = 0x60;
+        let ncpu = run_program(program, |_| {});
+        assert!(ncpu.call_stack().is_empty());
+        assert_eq!(ncpu.stack_pointer, STACKRESET - 3);
+    }
+
+    #[test]
+    fn call_stack_should_drop_frames_discarded_from_stack() {
+        //JSR $8004; BRK; $8004: PLA; PLA; BRK
+        let ncpu = run_program(vec![0x20, 0x04, 0x80, 0x00, 0x68, 0x68, 0x00], |_| {});
+        assert!(ncpu.call_stack().is_empty());
+        //JSR $8004; BRK; $8004: TXS; BRK
+        let ncpu = run_program(vec![0x20, 0x04, 0x80, 0x00, 0x9a, 0x00], |ncpu| {
+            ncpu.register_x = STACKRESET;
+        });
+        assert!(ncpu.call_stack().is_empty());
+    }
+
+    #[test]
+    fn call_stack_should_survive_stack_wrap() {
+        //$8000: JSR $8004; BRK; $8004: JSR $8008; BRK; $8008: PHA; PHA; PLA; PLA; RTS
+        let program = vec![
+            0x20, 0x04, 0x80, 0x00, 0x20, 0x08, 0x80, 0x00, 0x48, 0x48, 0x68, 0x68, 0x60,
+        ];
+        let mut ncpu = load_program(program);
+        ncpu.stack_pointer = 0x01;
+        ncpu.step().unwrap();
+        //返回地址压在$0101和$0100，栈指针回绕到$FF
+        assert_eq!(ncpu.stack_pointer, 0xff);
+        assert_eq!(ncpu.call_stack().len(), 1);
+        ncpu.step().unwrap();
+        assert_eq!(ncpu.stack_pointer, 0xfd);
+        assert_eq!(ncpu.call_stack().len(), 2);
+        for _ in 0..4 {
+            ncpu.step().unwrap();
+            assert_eq!(ncpu.call_stack().len(), 2);
+        }
+        //RTS回到$8007，内层帧出栈，外层帧保留
+        ncpu.step().unwrap();
+        assert_eq!(ncpu.program_counter(), 0x8007);
+        assert_eq!(ncpu.call_stack().len(), 1);
+        assert_eq!(ncpu.call_stack()[0].return_address, 0x8003);
+        //弹出的字节越过$01FF回到$0100，外层帧也出栈
+        assert_eq!(ncpu.stack_pop(), 0x02);
+        assert!(ncpu.call_stack().is_empty());
+        assert_eq!(ncpu.stack_pointer, 0x00);
+    }
+
+    #[test]
+    fn call_stack_should_keep_newest_frames_in_deep_recursion() {
+        //$8000: JSR $8000; RTS，无限递归200层，栈指针回绕了不止一圈
+        let mut ncpu = load_program(vec![0x20, 0x00, 0x80, 0x60]);
+        for _ in 0..200 {
+            ncpu.step().unwrap();
+        }
+        assert_eq!(ncpu.call_stack().len(), CALLSTACKMAXFRAMES);
+        assert_eq!(
+            ncpu.call_stack().last().unwrap().stack_pointer,
+            ncpu.stack_pointer
+        );
+        //$8000改成RTS，每次返回只弹出一帧
+        ncpu.write_to_memory_u8(0x8000, 0x60);
+        for frames in (0..CALLSTACKMAXFRAMES).rev() {
+            ncpu.step().unwrap();
+            assert_eq!(ncpu.call_stack().len(), frames);
+        }
+    }
+
+    #[test]
+    fn stack_pointer_should_wrap_within_page_one() {
+        let mut ncpu = CPU::new();
+        ncpu.stack_pointer = 0x00;
+        ncpu.stack_push_u16(0x1234);
+        assert_eq!(ncpu.stack_pointer, 0xfe);
+        assert_eq!(ncpu.read_from_memory_u8(0x0100), 0x12);
+        assert_eq!(ncpu.read_from_memory_u8(0x01ff), 0x34);
+        assert_eq!(ncpu.stack_pop_u16(), 0x1234);
+        assert_eq!(ncpu.stack_pointer, 0x00);
+    }
+
+    #[test]
+    fn pha_pla_should_work() {
+        //PHA; LDA #$00; PLA
+        let ncpu = run_program(vec![0x48, 0xa9, 0x00, 0x68, 0x00], |ncpu| {
+            ncpu.register_a = 0x55
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+        assert_eq!(ncpu.stack_pointer, STACKRESET - 3);
+    }
+
+    #[test]
+    fn pla_flags_should_work() {
+        let ncpu = run_program(vec![0x48, 0xa9, 0x01, 0x68, 0x00], |ncpu| {
+            ncpu.register_a = 0x00
+        });
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+    }
+
+    #[test]
+    fn php_should_work() {
+        let ncpu = run_program(vec![0x08, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x01fd), 0b0011_0101);
+    }
+
+    #[test]
+    fn plp_should_work() {
+        //PHP; CLC; PLP
+        let ncpu = run_program(vec![0x08, 0x18, 0x28, 0x00], |ncpu| {
+            ncpu.setstatus(StatusType::CarryFlag, true);
+        });
+        assert_eq!(ncpu.status.bits(), 0b0010_0101); //C，以及BRK置位的I，B不在寄存器中
+        assert_eq!(ncpu.stack_pointer, STACKRESET - 3);
+    }
+
+    #[test]
+    fn rti_should_work() {
+        let ncpu = run_program(vec![0x40, 0x00, 0x00, 0x00, 0x00, 0xe8, 0x00], |ncpu| {
+            ncpu.stack_push_u16(0x8005);
+            ncpu.stack_push(0b0001_0001);
+        });
+        assert_eq!(ncpu.register_x, 0x01);
+        assert_eq!(ncpu.status.bits(), 0b0010_0101); //C，以及BRK置位的I，B不在寄存器中
+        assert_eq!(ncpu.stack_pointer, STACKRESET - 3);
+    }
+
+    #[test]
+    fn brk_should_work() {
+        let ncpu = run_program(vec![0x00], |ncpu| {
+            ncpu.write_to_memory_u16(IRQADDRESS, 0x9000);
+            ncpu.setstatus(StatusType::CarryFlag, true);
+        });
+        assert_eq!(ncpu.program_counter, 0x9000);
+        assert_eq!(ncpu.stack_pointer, STACKRESET - 3);
+        assert_eq!(ncpu.read_from_memory_u16(0x01fc), 0x8002);
+        assert_eq!(brk_pushed_status(&ncpu), 0b0011_0101);
+        assert!(ncpu.getstatus(StatusType::InterruptDisable));
+    }
+
+    //除去复位序列和结尾BRK的周期数
+    fn program_cycles<F: FnOnce(&mut CPU)>(program: Vec<u8>, setup: F) -> u64 {
+        run_program(program, setup).cycles() - RESETCYCLES - 7
+    }
+
+    #[test]
+    fn reset_cycles_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.load(vec![0x00]).unwrap();
+        ncpu.power_on();
+        assert_eq!(ncpu.cycles(), 7);
+    }
+
+    #[test]
+    fn base_cycles_should_work() {
+        assert_eq!(program_cycles(vec![0xa9, 0x01], |_| {}), 2);
+        assert_eq!(program_cycles(vec![0xad, 0x00, 0x02], |_| {}), 4);
+        assert_eq!(
+            program_cycles(vec![0x20, 0x04, 0x80, 0x00, 0x60], |_| {}),
+            12
+        );
+    }
+
+    #[test]
+    fn absolute_x_page_cross_cycles_should_work() {
+        let program = vec![0xbd, 0xff, 0x02];
+        assert_eq!(program_cycles(program.clone(), |_| {}), 4);
+        assert_eq!(program_cycles(program, |ncpu| ncpu.register_x = 0x01), 5);
+    }
+
+    #[test]
+    fn absolute_y_page_cross_cycles_should_work() {
+        let program = vec![0xb9, 0xff, 0x02];
+        assert_eq!(program_cycles(program.clone(), |_| {}), 4);
+        assert_eq!(program_cycles(program, |ncpu| ncpu.register_y = 0x01), 5);
+    }
+
+    #[test]
+    fn indirect_indexed_page_cross_cycles_should_work() {
+        let setup = |ncpu: &mut CPU| ncpu.write_to_memory_u16(0x10, 0x02ff);
+        assert_eq!(program_cycles(vec![0xb1, 0x10], setup), 5);
+        assert_eq!(
+            program_cycles(vec![0xb1, 0x10], |ncpu| {
+                setup(ncpu);
+                ncpu.register_y = 0x01;
+            }),
+            6
+        );
+    }
+
+    #[test]
+    fn store_and_rmw_page_cross_cycles_should_work() {
+        let setup = |ncpu: &mut CPU| ncpu.register_x = 0x01;
+        assert_eq!(program_cycles(vec![0x9d, 0xff, 0x02], setup), 5);
+        assert_eq!(program_cycles(vec![0x1e, 0xff, 0x02], setup), 7);
+    }
+
+    #[test]
+    fn branch_cycles_should_work() {
+        //不跳转
+        assert_eq!(
+            program_cycles(vec![0xd0, 0x01, 0x00], |ncpu| {
+                ncpu.setstatus(StatusType::ZeroFlag, true)
+            }),
+            2
+        );
+        //同页跳转
+        assert_eq!(program_cycles(vec![0xd0, 0x01, 0x00], |_| {}), 3);
+        //跳到0x7fff，跨页
+        assert_eq!(program_cycles(vec![0xd0, 0xfd], |_| {}), 4);
+    }
+
+    #[test]
+    fn loop_cycles_should_work() {
+        //LDX #$03; DEX; BNE -3
+        assert_eq!(
+            program_cycles(vec![0xa2, 0x03, 0xca, 0xd0, 0xfd], |_| {}),
+            16
+        );
+    }
+
+    #[test]
+    fn unofficial_nop_should_work() {
+        //NOP imp; NOP #imm; NOP zp; NOP zp,X; NOP abs; NOP abs,X
+        let program = vec![
+            0x1a, 0x80, 0x01, 0x04, 0x10, 0x14, 0x10, 0x0c, 0x00, 0x02, 0x1c, 0x00, 0x02,
+        ];
+        let ncpu = run_program(program, |_| {});
+        assert_eq!(brk_address(&ncpu), 0x800d);
+        assert_eq!(ncpu.register_a, 0x00);
+        assert_eq!(ncpu.cycles() - RESETCYCLES - 7, 2 + 2 + 3 + 4 + 4 + 4);
+    }
+
+    #[test]
+    fn unofficial_nop_page_cross_cycles_should_work() {
+        assert_eq!(
+            program_cycles(vec![0x3c, 0xff, 0x02], |ncpu| ncpu.register_x = 0x01),
+            5
+        );
+    }
+
+    #[test]
+    fn lax_zeropage_should_work() {
+        let ncpu = run_program(vec![0xa7, 0x10, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x10, 0x55);
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+        assert_eq!(ncpu.register_x, 0x55);
+    }
+
+    #[test]
+    fn lax_zeropage_y_should_work() {
+        let ncpu = run_program(vec![0xb7, 0x10, 0x00], |ncpu| {
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u8(0x11, 0x55);
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+        assert_eq!(ncpu.register_x, 0x55);
+    }
+
+    #[test]
+    fn lax_indirect_indexed_should_work() {
+        let ncpu = run_program(vec![0xb3, 0x10, 0x00], |ncpu| {
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u16(0x10, 0x0200);
+            ncpu.write_to_memory_u8(0x0201, 0x80);
+        });
+        assert_eq!(ncpu.register_a, 0x80);
+        assert_eq!(ncpu.register_x, 0x80);
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn sax_should_work() {
+        let ncpu = run_program(vec![0x87, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x3c;
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x10), 0x30);
+    }
+
+    #[test]
+    fn sax_zeropage_y_should_work() {
+        let ncpu = run_program(vec![0x97, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xff;
+            ncpu.register_x = 0x55;
+            ncpu.register_y = 0x01;
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x11), 0x55);
+    }
+
+    #[test]
+    fn unofficial_sbc_should_work() {
+        let ncpu = run_program(vec![0xeb, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x30;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+        });
+        assert_eq!(ncpu.register_a, 0x20);
+    }
+
+    #[test]
+    fn dcp_should_work() {
+        let ncpu = run_program(vec![0xc7, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x20;
+            ncpu.write_to_memory_u8(0x10, 0x21);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x10), 0x20);
+        assert!(ncpu.getstatus(StatusType::ZeroFlag));
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn isb_should_work() {
+        let ncpu = run_program(vec![0xef, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0x30;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.write_to_memory_u8(0x0200, 0x0f);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x10);
+        assert_eq!(ncpu.register_a, 0x20);
+    }
+
+    #[test]
+    fn slo_should_work() {
+        let ncpu = run_program(vec![0x07, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x01;
+            ncpu.write_to_memory_u8(0x10, 0x81);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x10), 0x02);
+        assert_eq!(ncpu.register_a, 0x03);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn rla_should_work() {
+        let ncpu = run_program(vec![0x27, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x0f;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+            ncpu.write_to_memory_u8(0x10, 0x81);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x10), 0x03);
+        assert_eq!(ncpu.register_a, 0x03);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn sre_should_work() {
+        let ncpu = run_program(vec![0x47, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xff;
+            ncpu.write_to_memory_u8(0x10, 0x81);
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x10), 0x40);
+        assert_eq!(ncpu.register_a, 0xbf);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn rra_should_work() {
+        let ncpu = run_program(vec![0x67, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0x10;
+            ncpu.write_to_memory_u8(0x10, 0x03);
+        });
+        //ROR: 0x03 -> 0x01，C = 1；ADC: 0x10 + 0x01 + 1
+        assert_eq!(ncpu.read_from_memory_u8(0x10), 0x01);
+        assert_eq!(ncpu.register_a, 0x12);
+    }
+
+    #[test]
+    fn rmw_unofficial_cycles_should_work() {
+        let setup = |ncpu: &mut CPU| {
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u16(0x10, 0x02ff);
+        };
+        assert_eq!(program_cycles(vec![0xc7, 0x10], |_| {}), 5);
+        assert_eq!(program_cycles(vec![0xdb, 0xff, 0x02], setup), 7);
+        assert_eq!(program_cycles(vec![0xd3, 0x10], setup), 8);
+    }
+
+    #[test]
+    fn anc_should_work() {
+        let ncpu = run_program(vec![0x0b, 0x80, 0x00], |ncpu| ncpu.register_a = 0xff);
+        assert_eq!(ncpu.register_a, 0x80);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn alr_should_work() {
+        let ncpu = run_program(vec![0x4b, 0x03, 0x00], |ncpu| ncpu.register_a = 0xff);
+        assert_eq!(ncpu.register_a, 0x01);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn arr_should_work() {
+        let ncpu = run_program(vec![0x6b, 0xc0, 0x00], |ncpu| {
+            ncpu.register_a = 0xff;
+            ncpu.setstatus(StatusType::CarryFlag, true);
+        });
+        //(0xff & 0xc0) ROR，C移入第7位
+        assert_eq!(ncpu.register_a, 0xe0);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(!ncpu.getstatus(StatusType::OverflowFlag));
+        assert!(ncpu.getstatus(StatusType::NegativeFlag));
+    }
+
+    #[test]
+    fn arr_overflow_should_work() {
+        let ncpu = run_program(vec![0x6b, 0x80, 0x00], |ncpu| ncpu.register_a = 0xff);
+        assert_eq!(ncpu.register_a, 0x40);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        assert!(ncpu.getstatus(StatusType::OverflowFlag));
+    }
+
+    #[test]
+    fn axs_should_work() {
+        let ncpu = run_program(vec![0xcb, 0x10, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x3c;
+        });
+        assert_eq!(ncpu.register_x, 0x20);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        let ncpu = run_program(vec![0xcb, 0x31, 0x00], |ncpu| {
+            ncpu.register_a = 0xf0;
+            ncpu.register_x = 0x3c;
+        });
+        assert_eq!(ncpu.register_x, 0xff);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn xaa_should_work() {
+        let ncpu = run_program(vec![0x8b, 0xff, 0x00], |ncpu| {
+            ncpu.register_a = 0x00;
+            ncpu.register_x = 0x3f;
+        });
+        assert_eq!(ncpu.register_a, 0x2e);
+    }
+
+    #[test]
+    fn lxa_should_work() {
+        let ncpu = run_program(vec![0xab, 0x0f, 0x00], |_| {});
+        assert_eq!(ncpu.register_a, 0x0e);
+        assert_eq!(ncpu.register_x, 0x0e);
+    }
+
+    #[test]
+    fn shx_should_work() {
+        let ncpu = run_program(vec![0x9e, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_x = 0xff;
+            ncpu.register_y = 0x01;
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0201), 0x03);
+    }
+
+    #[test]
+    fn shy_page_cross_should_work() {
+        //跨页时高字节被写入值替换：0x02ff + 1 -> (0x03 & 0x03) << 8 | 0x00
+        let ncpu = run_program(vec![0x9c, 0xff, 0x02, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.register_y = 0xff;
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0300), 0x03);
+    }
+
+    #[test]
+    fn ahx_should_work() {
+        let ncpu = run_program(vec![0x9f, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0xff;
+            ncpu.register_x = 0xf3;
+        });
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x03);
+    }
+
+    #[test]
+    fn tas_should_work() {
+        let ncpu = run_program(vec![0x9b, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.register_a = 0xff;
+            ncpu.register_x = 0xf3;
+        });
+        assert_eq!(ncpu.stack_pointer, 0xf3 - 3);
+        assert_eq!(ncpu.read_from_memory_u8(0x0200), 0x03);
+    }
+
+    #[test]
+    fn las_should_work() {
+        let ncpu = run_program(vec![0xbb, 0x00, 0x02, 0x00], |ncpu| {
+            ncpu.write_to_memory_u8(0x0200, 0x0f);
+        });
+        assert_eq!(ncpu.register_a, 0x0d);
+        assert_eq!(ncpu.register_x, 0x0d);
+        //BRK压栈3字节
+        assert_eq!(ncpu.stack_pointer, 0x0d - 3);
+    }
+
+    #[test]
+    fn kil_should_work() {
+        let mut ncpu = CPU::new();
+        ncpu.load(vec![0xe8, 0x02, 0xe8, 0x00]).unwrap();
+        ncpu.power_on();
+        let jammed = CpuError::Jammed {
+            opcode: 0x02,
+            address: 0x8001,
+        };
+        assert_eq!(ncpu.run(), Err(jammed));
+        assert!(ncpu.jammed());
+        assert_eq!(ncpu.program_counter, 0x8001);
+        assert_eq!(ncpu.register_x, 0x01);
+        //停机后再次运行不会继续执行
+        assert_eq!(ncpu.run(), Err(jammed));
+        assert_eq!(ncpu.register_x, 0x01);
+        ncpu.power_on();
+        assert!(!ncpu.jammed());
+    }
+
+    //中断处理程序放在0x9000，NMI和IRQ/BRK共用
+    fn load_with_handler(program: Vec<u8>, handler: Vec<u8>) -> CPU {
+        let mut ncpu = CPU::new();
+        ncpu.load(program).unwrap();
+        ncpu.power_on();
+        ncpu.write_to_memory_u16(NMIADDRESS, 0x9000);
+        ncpu.write_to_memory_u16(IRQADDRESS, 0x9000);
+        for (i, byte) in handler.iter().enumerate() {
+            ncpu.write_to_memory_u8(0x9000 + i as u16, *byte);
+        }
+        ncpu
+    }
+
+    #[test]
+    fn nmi_should_work() {
+        //INY; RTI
+        let mut ncpu = load_with_handler(vec![0xe8, 0x00], vec![0xc8, 0x40]);
+        ncpu.set_nmi_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_x, 0x01);
+        assert_eq!(ncpu.register_y, 0x01);
+        //复位 + NMI + INY + RTI + INX + BRK
+        assert_eq!(ncpu.cycles(), 7 + 7 + 2 + 6 + 2 + 7);
+    }
+
+    #[test]
+    fn nmi_pushes_status_without_break_flag() {
+        let mut ncpu = load_with_handler(vec![0xe8, 0x00], vec![0x00]);
+        ncpu.setstatus(StatusType::CarryFlag, true);
+        ncpu.set_nmi_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_x, 0x00);
+        assert_eq!(ncpu.read_from_memory_u16(0x01fc), 0x8000);
+        assert_eq!(ncpu.read_from_memory_u8(0x01fb), 0b0010_0101);
+        assert!(ncpu.getstatus(StatusType::InterruptDisable));
+    }
+
+    #[test]
+    fn nmi_is_edge_triggered() {
+        let mut ncpu = load_with_handler(vec![0xe8, 0xe8, 0x00], vec![0xc8, 0x40]);
+        ncpu.set_nmi_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_y, 0x01);
+        //保持有效不会再次触发，需要先释放
+        ncpu.program_counter = 0x8000;
+        ncpu.set_nmi_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_y, 0x01);
+        ncpu.program_counter = 0x8000;
+        ncpu.set_nmi_line(false);
+        ncpu.set_nmi_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_y, 0x02);
+    }
+
+    #[test]
+    fn nmi_ignores_interrupt_disable() {
+        let mut ncpu = load_with_handler(vec![0xe8, 0x00], vec![0xc8, 0x40]);
+        ncpu.setstatus(StatusType::InterruptDisable, true);
+        ncpu.set_nmi_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_y, 0x01);
+    }
+
+    #[test]
+    fn irq_is_level_triggered() {
+        //INY; CPY #$03; BEQ +1; RTI; BRK
+        let handler = vec![0xc8, 0xc0, 0x03, 0xf0, 0x01, 0x40, 0x00];
+        let mut ncpu = load_with_handler(vec![0xe8, 0x00], handler);
+        ncpu.setstatus(StatusType::InterruptDisable, false);
+        ncpu.set_irq_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_y, 0x03);
+        assert_eq!(ncpu.register_x, 0x00);
+    }
+
+    #[test]
+    fn irq_masked_by_interrupt_disable() {
+        let mut ncpu = load_with_handler(vec![0xe8, 0x00], vec![0xc8, 0x40]);
+        ncpu.setstatus(StatusType::InterruptDisable, true);
+        ncpu.set_irq_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_x, 0x01);
+        assert_eq!(ncpu.register_y, 0x00);
+    }
+
+    #[test]
+    fn irq_after_cli_should_wait_one_instruction() {
+        //SEI; CLI; INX; INX; BRK，CLI之后还要执行一条INX才响应IRQ，处理程序：INY; BRK
+        let mut ncpu = load_with_handler(vec![0x78, 0x58, 0xe8, 0xe8, 0x00], vec![0xc8, 0x00]);
+        ncpu.setstatus(StatusType::InterruptDisable, true);
+        ncpu.set_irq_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_y, 0x01);
+        assert_eq!(ncpu.register_x, 0x01);
+        assert_eq!(ncpu.read_from_memory_u16(0x01fc), 0x8003);
+    }
+
+    #[test]
+    fn irq_between_cli_and_sei_should_be_taken_after_sei() {
+        //CLI; SEI; INX; BRK，SEI轮询时I还是0，压入的状态中I已经置位
+        let mut ncpu = load_with_handler(vec![0x58, 0x78, 0xe8, 0x00], vec![0xc8, 0x00]);
+        ncpu.setstatus(StatusType::InterruptDisable, true);
+        ncpu.set_irq_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_y, 0x01);
+        assert_eq!(ncpu.register_x, 0x00);
+        assert_eq!(ncpu.read_from_memory_u16(0x01fc), 0x8002);
+        assert_eq!(ncpu.read_from_memory_u8(0x01fb) & 0b0000_0100, 0b0000_0100);
+    }
+
+    #[test]
+    fn irq_after_plp_should_wait_one_instruction() {
+        //LDA #$00; PHA; PLP; INX; INX; BRK
+        let program = vec![0xa9, 0x00, 0x48, 0x28, 0xe8, 0xe8, 0x00];
+        let mut ncpu = load_with_handler(program, vec![0xc8, 0x00]);
+        ncpu.setstatus(StatusType::InterruptDisable, true);
+        ncpu.set_irq_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_y, 0x01);
+        assert_eq!(ncpu.register_x, 0x01);
+        assert_eq!(ncpu.read_from_memory_u16(0x01fc), 0x8005);
+    }
+
+    #[test]
+    fn nmi_has_priority_over_irq() {
+        let mut ncpu = load_with_handler(vec![0xe8, 0x00], vec![0x00]);
+        ncpu.write_to_memory_u16(IRQADDRESS, 0xa000);
+        ncpu.set_irq_line(true);
+        ncpu.set_nmi_line(true);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.program_counter, 0xa000);
+        //NMI压入的返回地址
+        assert_eq!(ncpu.read_from_memory_u16(0x01fc), 0x8000);
+        //NMI处理程序中的BRK压入的返回地址
+        assert_eq!(ncpu.read_from_memory_u16(0x01f9), 0x9002);
+    }
+
+    //从上电开始计数周期，到nmi_cycle之后总线的NMI线一直有效
+    struct NmiAtCycle {
+        memory: FlatMemory,
+        cycles: u64,
+        nmi_cycle: u64,
+    }
+
+    impl Memory for NmiAtCycle {
+        fn read(&mut self, add: u16) -> u8 {
+            self.memory.read(add)
+        }
+
+        fn write(&mut self, add: u16, value: u8) {
+            self.memory.write(add, value);
+        }
+
+        fn peek(&self, add: u16) -> u8 {
+            self.memory.peek(add)
+        }
+
+        fn power_on(&mut self) {
+            self.cycles = 0;
+        }
+
+        fn tick(&mut self) {
+            self.cycles += 1;
+        }
+
+        fn nmi(&self) -> bool {
+            self.cycles >= self.nmi_cycle
+        }
+    }
+
+    //BRK在0x8000，NMI处理程序在0x9000，IRQ/BRK处理程序在0xA000，都以NOP开始
+    fn brk_with_nmi_at(nmi_cycle: u64) -> CPU<NmiAtCycle> {
+        let memory = NmiAtCycle {
+            memory: FlatMemory::new(),
+            cycles: 0,
+            nmi_cycle,
+        };
+        let mut ncpu = CPU::with_memory(memory);
+        ncpu.load(vec![0x00]).unwrap();
+        ncpu.write_to_memory_u16(NMIADDRESS, 0x9000);
+        ncpu.write_to_memory_u16(IRQADDRESS, 0xa000);
+        ncpu.write_to_memory_u8(0x9000, 0xea);
+        ncpu.write_to_memory_u8(0xa000, 0xea);
+        ncpu.power_on();
+        ncpu
+    }
+
+    #[test]
+    fn nmi_hijacks_brk() {
+        //复位占用第1-7个周期，BRK在第10个周期压栈时NMI到来
+        let mut ncpu = brk_with_nmi_at(10);
+        let result = ncpu.step().unwrap();
+        assert_eq!(result.event, StepEvent::Break);
+        assert_eq!(result.interrupt, None);
+        assert_eq!(ncpu.program_counter, 0x9000);
+        assert_eq!(ncpu.read_from_memory_u16(0x01fc), 0x8002);
+        assert_eq!(ncpu.read_from_memory_u8(0x01fb) & 0b0001_0000, 0b0001_0000);
+        assert!(!ncpu.nmi_pending);
+        //NMI已经被BRK响应，不会再进入一次
+        assert_eq!(ncpu.step().unwrap().interrupt, None);
+    }
+
+    #[test]
+    fn late_nmi_should_follow_brk() {
+        //BRK读向量时才到来的NMI不能劫持，在处理程序第一条指令之后响应
+        let mut ncpu = brk_with_nmi_at(13);
+        ncpu.step().unwrap();
+        assert_eq!(ncpu.program_counter, 0xa000);
+        let result = ncpu.step().unwrap();
+        assert_eq!(result.interrupt, None);
+        assert_eq!(result.address, 0xa000);
+        let result = ncpu.step().unwrap();
+        assert_eq!(result.interrupt, Some(Interrupt::Nmi));
+        assert_eq!(result.address, 0x9000);
+    }
+
+    #[test]
+    fn nmi_should_be_polled_before_last_cycle() {
+        //LDA $0200（4个周期）从第8个周期开始，NMI在倒数第二个周期到来时指令结束后响应，
+        //在最后一个周期到来时要再执行一条指令
+        for (nmi_cycle, address) in [(10, 0x9000), (11, 0x8003)] {
+            let memory = NmiAtCycle {
+                memory: FlatMemory::new(),
+                cycles: 0,
+                nmi_cycle,
+            };
+            let mut ncpu = CPU::with_memory(memory);
+            ncpu.load(vec![0xad, 0x00, 0x02, 0xea, 0xea]).unwrap();
+            ncpu.write_to_memory_u16(NMIADDRESS, 0x9000);
+            ncpu.power_on();
+            ncpu.step().unwrap();
+            assert_eq!(ncpu.step().unwrap().address, address);
+        }
+    }
+
+    #[test]
+    fn brk_rti_should_work() {
+        //BRK; 填充字节; INX; BRK，处理程序：INY; RTI
+        let mut ncpu = load_with_handler(vec![0x00, 0xff, 0xe8, 0x00], vec![0xc8, 0x40]);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.program_counter, 0x9000);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.register_x, 0x01);
+        assert_eq!(ncpu.register_y, 0x01);
+    }
+
+    fn load_program(program: Vec<u8>) -> CPU {
+        let mut ncpu = CPU::new();
+        ncpu.load(program).unwrap();
+        ncpu.power_on();
+        ncpu
+    }
+
+    #[test]
+    fn step_should_work() {
+        let mut ncpu = load_program(vec![0xa9, 0x55, 0xe8, 0x00]);
+        let result = ncpu.step().unwrap();
+        assert_eq!(
+            result,
+            StepResult {
+                address: 0x8000,
+                opcode: 0xa9,
+                cycles: 2,
+                interrupt: None,
+                event: StepEvent::Normal,
+            }
+        );
+        assert_eq!(ncpu.register_a, 0x55);
+        assert_eq!(ncpu.program_counter(), 0x8002);
+        assert_eq!(ncpu.register_x, 0x00);
+        assert_eq!(ncpu.step().unwrap().address, 0x8002);
+        assert_eq!(ncpu.register_x, 0x01);
+        assert_eq!(ncpu.step().unwrap().event, StepEvent::Break);
+    }
+
+    #[test]
+    fn step_page_cross_cycles_should_work() {
+        let mut ncpu = load_program(vec![0xbd, 0xff, 0x02]);
+        ncpu.register_x = 0x01;
+        assert_eq!(ncpu.step().unwrap().cycles, 5);
+    }
+
+    #[test]
+    fn step_trapped_should_work() {
+        //JMP $8000
+        let mut ncpu = load_program(vec![0x4c, 0x00, 0x80]);
+        assert_eq!(ncpu.step().unwrap().event, StepEvent::Trapped);
+        //BNE *
+        let mut ncpu = load_program(vec![0xd0, 0xfe]);
+        assert_eq!(ncpu.step().unwrap().event, StepEvent::Trapped);
+    }
+
+    #[test]
+    fn step_halted_should_work() {
+        let mut ncpu = load_program(vec![0x02]);
+        let result = ncpu.step().unwrap();
+        assert_eq!(result.event, StepEvent::Halted);
+        assert_eq!(result.opcode, 0x02);
+        assert_eq!(
+            ncpu.step(),
+            Err(CpuError::Jammed {
+                opcode: 0x02,
+                address: 0x8000
+            })
+        );
+    }
+
+    #[test]
+    fn step_interrupt_should_work() {
+        let mut ncpu = load_with_handler(vec![0xe8, 0x00], vec![0xc8, 0x40]);
+        ncpu.set_nmi_line(true);
+        let result = ncpu.step().unwrap();
+        assert_eq!(result.interrupt, Some(Interrupt::Nmi));
+        assert_eq!(result.address, 0x9000);
+        assert_eq!(result.opcode, 0xc8);
+        assert_eq!(result.cycles, 7 + 2);
+        assert_eq!(ncpu.step().unwrap().opcode, 0x40);
+        assert_eq!(ncpu.step().unwrap().opcode, 0xe8);
+    }
+
+    #[test]
+    fn branch_not_taken_advances_pc() {
+        let mut ncpu = load_program(vec![0xf0, 0x10, 0xe8]);
+        ncpu.step().unwrap();
+        assert_eq!(ncpu.program_counter(), 0x8002);
+    }
+
+    #[test]
+    fn run_for_cycles_should_work() {
+        //INX; JMP $8000
+        let mut ncpu = load_program(vec![0xe8, 0x4c, 0x00, 0x80]);
+        assert_eq!(ncpu.run_for_cycles(100).unwrap(), 100);
+        assert_eq!(ncpu.register_x, 20);
+        //不会在指令中间停下，可能多执行几个周期
+        assert_eq!(ncpu.run_for_cycles(1).unwrap(), 2);
+        assert_eq!(ncpu.cycles(), RESETCYCLES + 102);
+    }
+
+    #[test]
+    fn run_for_cycles_stops_when_halted() {
+        let mut ncpu = load_program(vec![0xe8, 0x02]);
+        assert!(matches!(
+            ncpu.run_for_cycles(100),
+            Err(CpuError::Jammed { .. })
+        ));
+        assert!(ncpu.jammed());
+        assert_eq!(ncpu.cycles(), RESETCYCLES + 4);
+    }
+
+    #[test]
+    fn run_until_should_work() {
+        //LDX #$00; INX; JMP $8002
+        let mut ncpu = load_program(vec![0xa2, 0x00, 0xe8, 0x4c, 0x02, 0x80]);
+        let result = ncpu.run_until(|cpu, _| cpu.register_x == 5).unwrap();
+        assert_eq!(result.opcode, 0xe8);
+        assert_eq!(ncpu.program_counter(), 0x8003);
+        let result = ncpu.run_until(|_, result| result.opcode == 0x4c).unwrap();
+        assert_eq!(result.address, 0x8003);
+        assert_eq!(ncpu.program_counter(), 0x8002);
+    }
+
+    #[test]
+    fn load_too_large_should_fail() {
         let mut ncpu = CPU::new();
-        ncpu.load(vec![0xa5, 0x10, 0x00]);
-        ncpu.reset();
-        assert_eq!(ncpu.program_counter, 0x8000);
-        let operatecode = ncpu.read_from_memory_u8(ncpu.program_counter);
-        assert_eq!(operatecode, 0xa5);
+        assert_eq!(
+            ncpu.load(vec![0xea; 0x8001]),
+            Err(CpuError::ProgramTooLarge {
+                size: 0x8001,
+                max: 0x8000
+            })
+        );
+        assert!(ncpu.load(vec![0xea; 0x8000]).is_ok());
     }
 
     #[test]
-    fn lda_from_memory_should_work() {
+    fn load_full_image_should_keep_its_vectors() {
+        let mut program = vec![0xea; 0x8000];
+        program[0x7ffa..].copy_from_slice(&[0x00, 0x90, 0x00, 0xc0, 0x00, 0xa0]);
         let mut ncpu = CPU::new();
-        ncpu.write_to_memory_u8(0x10, 0x55);
+        ncpu.load(program).unwrap();
+        assert_eq!(ncpu.read_from_memory_u16(NMIADDRESS), 0x9000);
+        assert_eq!(ncpu.read_from_memory_u16(RESETADDRESS), 0xc000);
+        assert_eq!(ncpu.read_from_memory_u16(IRQADDRESS), 0xa000);
+        //不覆盖向量的程序仍然从0x8000开始
+        let mut ncpu = CPU::new();
+        ncpu.load(vec![0xea; 0x7ffc]).unwrap();
+        assert_eq!(ncpu.read_from_memory_u16(RESETADDRESS), 0x8000);
+    }
+
+    #[test]
+    fn load_into_rom_should_fail() {
+        let mut ncpu = CPU::with_memory(Bus::new(vec![0x00; 0x4000]));
+        assert_eq!(
+            ncpu.load(vec![0xa9, 0x01]),
+            Err(CpuError::ReadOnlyMemory(0x8000))
+        );
+    }
+
+    #[test]
+    fn invalid_addressing_mode_should_fail() {
+        let mut ncpu = CPU::new();
+        assert_eq!(
+            ncpu.get_operand_address(&AddressingModes::NoAddressingMode),
+            Err(CpuError::InvalidAddressingMode(
+                AddressingModes::NoAddressingMode
+            ))
+        );
+        assert!(ncpu.lda(&AddressingModes::Accumulator).is_err());
+    }
 
-        ncpu.load_and_run(vec![0xa5, 0x10, 0x00]);
+    #[test]
+    fn cpu_error_display_should_work() {
+        let error = CpuError::Jammed {
+            opcode: 0x02,
+            address: 0x8001,
+        };
+        assert_eq!(error.to_string(), "cpu jammed by opcode 02 at 8001");
+    }
 
+    #[test]
+    fn zero_page_x_should_wrap() {
+        let ncpu = run_program(vec![0xb5, 0xf0, 0x00], |ncpu| {
+            ncpu.register_x = 0x20;
+            ncpu.write_to_memory_u8(0x0010, 0x55);
+            ncpu.write_to_memory_u8(0x0110, 0xaa);
+        });
         assert_eq!(ncpu.register_a, 0x55);
     }
 
     #[test]
-    fn sta_should_work() {
-        let mut ncpu = CPU::new();
-        // ncpu.write_to_memory_u8(0x11, 0x10);
-        ncpu.load(vec![0x85, 0x10, 0x00]);
-        ncpu.reset();
+    fn zero_page_y_should_wrap() {
+        let ncpu = run_program(vec![0xb6, 0xff, 0x00], |ncpu| {
+            ncpu.register_x = 0x05;
+            ncpu.register_y = 0x02;
+            ncpu.write_to_memory_u8(0x0001, 0x55);
+            ncpu.write_to_memory_u8(0x0101, 0xaa);
+        });
+        assert_eq!(ncpu.register_x, 0x55);
+    }
+
+    #[test]
+    fn indexed_indirect_should_read_single_byte_operand() {
+        //操作数后面的字节是下一条指令，不能被当作操作数的高字节
+        let ncpu = run_program(vec![0xa1, 0x10, 0xe8, 0x00], |ncpu| {
+            ncpu.write_to_memory_u16(0x10, 0x0200);
+            ncpu.write_to_memory_u8(0x0200, 0x55);
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+        assert_eq!(ncpu.register_x, 0x01);
+    }
+
+    #[test]
+    fn indexed_indirect_should_wrap_in_zero_page() {
+        let ncpu = run_program(vec![0xa1, 0xf0, 0x00], |ncpu| {
+            ncpu.register_x = 0x20;
+            ncpu.write_to_memory_u16(0x10, 0x0200);
+            ncpu.write_to_memory_u8(0x0200, 0x55);
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn indexed_indirect_pointer_should_wrap_at_ff() {
+        let ncpu = run_program(vec![0xa1, 0xfe, 0x00], |ncpu| {
+            ncpu.register_x = 0x01;
+            ncpu.write_to_memory_u8(0x00ff, 0x00);
+            ncpu.write_to_memory_u8(0x0000, 0x03);
+            ncpu.write_to_memory_u8(0x0100, 0x04);
+            ncpu.write_to_memory_u8(0x0300, 0x55);
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn indirect_indexed_pointer_should_wrap_at_ff() {
+        let ncpu = run_program(vec![0xb1, 0xff, 0xe8, 0x00], |ncpu| {
+            ncpu.register_y = 0x01;
+            ncpu.write_to_memory_u8(0x00ff, 0x00);
+            ncpu.write_to_memory_u8(0x0000, 0x03);
+            ncpu.write_to_memory_u8(0x0100, 0x04);
+            ncpu.write_to_memory_u8(0x0301, 0x55);
+        });
+        assert_eq!(ncpu.register_a, 0x55);
+        assert_eq!(ncpu.register_x, 0x01);
+    }
+
+    #[test]
+    fn relative_should_branch_backwards() {
+        let source = "
+                LDX #3
+        loop:   DEX
+                BNE loop
+                BRK
+        ";
+        let ncpu = run_assembly(source, |_| {});
+        assert_eq!(ncpu.register_x, 0x00);
+        assert_eq!(brk_address(&ncpu), 0x8005);
+    }
+
+    #[test]
+    fn jmp_indirect_should_not_cross_page() {
+        let source = "
+                JMP ($02FF)
+                BRK
+                BRK
+                INX
+                BRK
+        ";
+        let ncpu = run_assembly(source, |ncpu| {
+            ncpu.write_to_memory_u8(0x02ff, 0x05);
+            ncpu.write_to_memory_u8(0x0200, 0x80);
+            ncpu.write_to_memory_u8(0x0300, 0x90);
+        });
+        assert_eq!(ncpu.register_x, 0x01);
+    }
+
+    #[test]
+    fn bus_accesses_should_match_table_cycles() {
+        //不跨页、不跳转时，逐周期执行的周期数应等于操作码表中的基本周期数
+        for instruction in INSTRUCTION_TABLE.iter().flatten() {
+            let opcode = instruction.opcode;
+            if matches!(opcode.addressmode, AddressingModes::Relative) {
+                continue;
+            }
+            let mut ncpu = load_program(vec![opcode.opcode, 0x10, 0x02]);
+            ncpu.write_to_memory_u16(0x0010, 0x0200);
+            let result = ncpu.step().unwrap();
+            assert_eq!(
+                result.cycles, opcode.cycles as u64,
+                "{} {:02X}",
+                opcode.opname, opcode.opcode
+            );
+        }
+    }
+
+    #[test]
+    fn read_modify_write_should_take_extra_cycles() {
+        //INC $10,X：取指、取操作数、空读基址、读、写回原值、写结果
+        let mut ncpu = load_program(vec![0xf6, 0x10]);
+        ncpu.register_x = 0x01;
+        ncpu.write_to_memory_u8(0x0011, 0x41);
+        assert_eq!(ncpu.step().unwrap().cycles, 6);
+        assert_eq!(ncpu.read_from_memory_u8(0x0011), 0x42);
+    }
+
+    //记录每一次总线访问：(地址, 值, 是否写)
+    struct RecordingMemory {
+        memory: FlatMemory,
+        accesses: Vec<(u16, u8, bool)>,
+    }
+
+    impl Memory for RecordingMemory {
+        fn read(&mut self, add: u16) -> u8 {
+            let value = self.memory.read(add);
+            self.accesses.push((add, value, false));
+            value
+        }
+
+        fn write(&mut self, add: u16, value: u8) {
+            self.accesses.push((add, value, true));
+            self.memory.write(add, value);
+        }
+
+        fn peek(&self, add: u16) -> u8 {
+            self.memory.peek(add)
+        }
+    }
+
+    fn recording_cpu(program: Vec<u8>) -> CPU<RecordingMemory> {
+        let mut ncpu = CPU::with_memory(RecordingMemory {
+            memory: FlatMemory::new(),
+            accesses: vec![],
+        });
+        ncpu.load(program).unwrap();
+        ncpu.power_on();
+        ncpu.memory_mut().accesses.clear();
+        ncpu
+    }
+
+    #[test]
+    fn absolute_x_read_should_dummy_read_on_page_cross() {
+        let mut ncpu = recording_cpu(vec![0xbd, 0xff, 0x02]);
+        ncpu.register_x = 0x01;
+        ncpu.step().unwrap();
+        assert_eq!(
+            ncpu.memory().accesses,
+            vec![
+                (0x8000, 0xbd, false),
+                (0x8001, 0xff, false),
+                (0x8002, 0x02, false),
+                (0x0200, 0x00, false),
+                (0x0300, 0x00, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn absolute_x_store_should_always_dummy_read() {
+        let mut ncpu = recording_cpu(vec![0x9d, 0x00, 0x02]);
+        ncpu.register_x = 0x01;
         ncpu.register_a = 0x55;
-        ncpu.run();
-        assert_eq!(ncpu.read_from_memory_u8(0x10), 0x55);
+        ncpu.step().unwrap();
+        assert_eq!(
+            &ncpu.memory().accesses[3..],
+            &[(0x0201, 0x00, false), (0x0201, 0x55, true)]
+        );
+    }
+
+    #[test]
+    fn read_modify_write_should_write_old_value_first() {
+        let mut ncpu = recording_cpu(vec![0xe6, 0x10]);
+        ncpu.write_to_memory_u8(0x0010, 0x41);
+        ncpu.memory_mut().accesses.clear();
+        ncpu.step().unwrap();
+        assert_eq!(
+            &ncpu.memory().accesses[2..],
+            &[
+                (0x0010, 0x41, false),
+                (0x0010, 0x41, true),
+                (0x0010, 0x42, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn cpu_should_run_on_nes_bus() {
+        //LDA #$55; STA $0801; BRK，程序放在16K PRG ROM的开头
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[..5].copy_from_slice(&[0xa9, 0x55, 0x8d, 0x01, 0x08]);
+        prg_rom[0x3ffc] = 0x00;
+        prg_rom[0x3ffd] = 0xc0;
+        let mut ncpu = CPU::with_memory(Bus::new(prg_rom));
+        ncpu.power_on();
+        assert_eq!(ncpu.program_counter(), 0xc000);
+        ncpu.run().unwrap();
+        assert_eq!(ncpu.read_from_memory_u8(0x0001), 0x55);
+    }
+
+    #[test]
+    fn trace_should_match_nestest_format() {
+        let mut ncpu = CPU::new();
+        ncpu.write_to_memory_u8(0xc000, 0x4c);
+        ncpu.write_to_memory_u16(0xc001, 0xc5f5);
+        ncpu.program_counter = 0xc000;
+        ncpu.status = Status::from_bits(0x24);
+        ncpu.cycles = 7;
+        assert_eq!(
+            ncpu.trace(),
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+        );
+    }
+
+    #[test]
+    fn trace_should_mark_unofficial_opcodes() {
+        let mut ncpu = CPU::new();
+        ncpu.write_to_memory_u8(0xc6bd, 0x04);
+        ncpu.write_to_memory_u8(0xc6be, 0xa9);
+        ncpu.program_counter = 0xc6bd;
+        ncpu.cycles = 120;
+        assert_eq!(
+            ncpu.trace(),
+            "C6BD  04 A9    *NOP $A9 = 00                    A:00 X:00 Y:00 P:20 SP:FD PPU:  1, 19 CYC:120"
+        );
+    }
+
+    #[test]
+    fn trace_should_resolve_indirect_operands() {
+        let mut ncpu = CPU::new();
+        ncpu.write_to_memory_u16(0x0080, 0x0200);
+        ncpu.write_to_memory_u8(0x0200, 0x5a);
+        ncpu.write_to_memory_u8(0x0201, 0x89);
+        ncpu.write_to_memory_u8(0xc000, 0xa1);
+        ncpu.write_to_memory_u8(0xc001, 0x80);
+        ncpu.write_to_memory_u8(0xc002, 0xb1);
+        ncpu.write_to_memory_u8(0xc003, 0x80);
+        ncpu.program_counter = 0xc000;
+        assert!(ncpu.trace().contains("LDA ($80,X) @ 80 = 0200 = 5A "));
+        ncpu.program_counter = 0xc002;
+        ncpu.register_y = 0x01;
+        assert!(ncpu.trace().contains("LDA ($80),Y = 0200 @ 0201 = 89 "));
+    }
+
+    //测试用的共享缓冲区，跟踪器写入后仍能读出内容
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tracer_should_write_one_line_per_instruction() {
+        let buffer = SharedBuffer::default();
+        let mut ncpu = load_program(vec![0xa9, 0x01, 0xaa, 0x00]);
+        ncpu.set_tracer(buffer.clone());
+        ncpu.run().unwrap();
+        let log = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("8000  A9 01     LDA #$01"));
+        assert!(lines[1].starts_with("8002  AA        TAX"));
+        assert!(lines[1].ends_with("A:01 X:00 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9"));
+        assert!(ncpu.take_tracer().is_some());
+    }
+
+    #[test]
+    fn trace_should_use_ppu_position_on_bus() {
+        //NOP循环：NOP; JMP $8000
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[..4].copy_from_slice(&[0xea, 0x4c, 0x00, 0x80]);
+        prg_rom[0x7ffd] = 0x80;
+        let mut bus = Bus::new(prg_rom);
+        bus.set_region(crate::nes::Region::Pal);
+        let mut ncpu = CPU::with_memory(bus);
+        ncpu.power_on();
+        //PAL每个CPU周期3.2个点，7个周期是22个点
+        assert!(ncpu.trace().ends_with("PPU:  0, 22 CYC:7"));
+        ncpu.run_for_cycles(200).unwrap();
+        let (scanline, dot) = {
+            let ppu = ncpu.memory().ppu();
+            (ppu.scanline(), ppu.dot())
+        };
+        let position = format!("PPU:{:>3},{:>3} CYC:{}", scanline, dot, ncpu.cycles());
+        assert!(ncpu.trace().ends_with(&position));
+    }
+
+    #[test]
+    fn set_registers_should_round_trip() {
+        let mut ncpu = CPU::new();
+        let registers = Registers {
+            register_a: 0x01,
+            register_x: 0x02,
+            register_y: 0x03,
+            status: 0x24,
+            stack_pointer: 0xf0,
+            program_counter: 0xc000,
+        };
+        ncpu.set_registers(registers);
+        assert_eq!(ncpu.registers(), registers);
+    }
+
+    #[test]
+    fn nested_subroutines_should_return() {
+        let source = "
+                JSR outer
+                BRK
+        outer:  JSR inner
+                INY
+                RTS
+        inner:  INX
+                RTS
+        ";
+        let ncpu = run_assembly(source, |_| {});
+        assert_eq!(ncpu.register_x, 0x01);
+        assert_eq!(ncpu.register_y, 0x01);
+        assert_eq!(brk_address(&ncpu), 0x8003);
+    }
+
+    fn run_nmos<F: FnOnce(&mut CPU)>(program: Vec<u8>, setup: F) -> CPU {
+        let mut ncpu = CPU::with_variant(FlatMemory::new(), Variant::Nmos6502);
+        ncpu.load(program).unwrap();
+        ncpu.power_on();
+        setup(&mut ncpu);
+        ncpu.run().unwrap();
+        ncpu
+    }
+
+    #[test]
+    fn adc_decimal_should_work_on_nmos() {
+        //SED; CLC; LDA #$15; ADC #$27
+        let ncpu = run_nmos(vec![0xf8, 0x18, 0xa9, 0x15, 0x69, 0x27, 0x00], |_| {});
+        assert_eq!(ncpu.register_a, 0x42);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+        //99 + 1 = 00，进位
+        let ncpu = run_nmos(vec![0xf8, 0x18, 0xa9, 0x99, 0x69, 0x01, 0x00], |_| {});
+        assert_eq!(ncpu.register_a, 0x00);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn sbc_decimal_should_work_on_nmos() {
+        //SED; SEC; LDA #$42; SBC #$15
+        let ncpu = run_nmos(vec![0xf8, 0x38, 0xa9, 0x42, 0xe9, 0x15, 0x00], |_| {});
+        assert_eq!(ncpu.register_a, 0x27);
+        assert!(ncpu.getstatus(StatusType::CarryFlag));
+        //00 - 01 = 99，借位
+        let ncpu = run_nmos(vec![0xf8, 0x38, 0xa9, 0x00, 0xe9, 0x01, 0x00], |_| {});
+        assert_eq!(ncpu.register_a, 0x99);
+        assert!(!ncpu.getstatus(StatusType::CarryFlag));
+    }
+
+    #[test]
+    fn adc_should_ignore_decimal_flag_on_2a03() {
+        let ncpu = run_program(vec![0xf8, 0x18, 0xa9, 0x15, 0x69, 0x27, 0x00], |_| {});
+        assert_eq!(ncpu.variant(), Variant::Ricoh2A03);
+        assert_eq!(ncpu.register_a, 0x3c);
     }
 }
@@ -1,7 +1,6 @@
 use lazy_static::lazy_static;
-use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AddressingModes {
     Immediate,
     ZeroPage,
@@ -14,6 +13,7 @@ pub enum AddressingModes {
     Indirect,
     IndexedIndirect,
     IndirectIndexed,
+    Accumulator,
     NoAddressingMode,
 }
 
@@ -23,6 +23,7 @@ pub struct OpCode {
     pub opname: &'static str,
     pub bytes: u8,
     pub cycles: u8,
+    pub unofficial: bool, //非官方（非法）指令
 }
 
 impl OpCode {
@@ -34,11 +35,25 @@ impl OpCode {
         cycles: u8,
     ) -> Self {
         OpCode {
-            addressmode: addressmode,
-            opcode: opcode,
-            opname: opname,
-            bytes: bytes,
-            cycles: cycles,
+            addressmode,
+            opcode,
+            opname,
+            bytes,
+            cycles,
+            unofficial: false,
+        }
+    }
+
+    fn unofficial(
+        opcode: u8,
+        opname: &'static str,
+        addressmode: AddressingModes,
+        bytes: u8,
+        cycles: u8,
+    ) -> Self {
+        OpCode {
+            unofficial: true,
+            ..OpCode::new(opcode, opname, addressmode, bytes, cycles)
         }
     }
 }
@@ -64,7 +79,7 @@ lazy_static! {
         OpCode::new(0x21,"AND",AddressingModes::IndexedIndirect,2,6),
         OpCode::new(0x31,"AND",AddressingModes::IndirectIndexed,2,5),
         //ASL
-        OpCode::new(0x0A,"ASL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::new(0x0A,"ASL",AddressingModes::Accumulator,1,2),
         OpCode::new(0x06,"ASL",AddressingModes::ZeroPage,2,5),
         OpCode::new(0x16,"ASL",AddressingModes::ZeroPageX,2,6),
         OpCode::new(0x0E,"ASL",AddressingModes::Absolute,3,6),
@@ -74,7 +89,7 @@ lazy_static! {
         //BCS
         OpCode::new(0xB0,"BCS",AddressingModes::Relative,2,2),
         //BEQ
-        OpCode::new(0xF0,"BEQ",AddressingModes::Immediate,2,2),
+        OpCode::new(0xF0,"BEQ",AddressingModes::Relative,2,2),
         //BIT
         OpCode::new(0x24,"BIT",AddressingModes::ZeroPage,2,3),
         OpCode::new(0x2C,"BIT",AddressingModes::Absolute,3,4),
@@ -155,7 +170,7 @@ lazy_static! {
         OpCode::new(0xbd,"LDA",AddressingModes::AbsoluteX,3,4),//+1 if page crossed
         OpCode::new(0xb9,"LDA",AddressingModes::AbsoluteY,3,4),//+1 if page crossed
         OpCode::new(0xa1,"LDA",AddressingModes::IndexedIndirect,2,6),
-        OpCode::new(0xba,"LDA",AddressingModes::IndirectIndexed,2,5),//+1 if page crossed
+        OpCode::new(0xb1,"LDA",AddressingModes::IndirectIndexed,2,5),//+1 if page crossed
         //LDX
         OpCode::new(0xa2,"LDX",AddressingModes::Immediate,2,2),
         OpCode::new(0xa6,"LDX",AddressingModes::ZeroPage,2,3),
@@ -166,10 +181,10 @@ lazy_static! {
         OpCode::new(0xa0,"LDY",AddressingModes::Immediate,2,2),
         OpCode::new(0xa4,"LDY",AddressingModes::ZeroPage,2,3),
         OpCode::new(0xb4,"LDY",AddressingModes::ZeroPageX,2,4),
-        OpCode::new(0xaC,"LDY",AddressingModes::Absolute,3,4),
-        OpCode::new(0xbC,"LDY",AddressingModes::AbsoluteX,3,4),
+        OpCode::new(0xac,"LDY",AddressingModes::Absolute,3,4),
+        OpCode::new(0xbc,"LDY",AddressingModes::AbsoluteX,3,4),
         //LSR
-        OpCode::new(0x4A,"LSR",AddressingModes::NoAddressingMode,1,2),
+        OpCode::new(0x4A,"LSR",AddressingModes::Accumulator,1,2),
         OpCode::new(0x46,"LSR",AddressingModes::ZeroPage,2,5),
         OpCode::new(0x56,"LSR",AddressingModes::ZeroPageX,2,6),
         OpCode::new(0x4E,"LSR",AddressingModes::Absolute,3,6),
@@ -194,17 +209,17 @@ lazy_static! {
         //PLP
         OpCode::new(0x28,"PLP",AddressingModes::NoAddressingMode,1,4),
         //ROL
-        OpCode::new(0x2A,"ROL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::new(0x2A,"ROL",AddressingModes::Accumulator,1,2),
         OpCode::new(0x26,"ROL",AddressingModes::ZeroPage,2,5),
         OpCode::new(0x36,"ROL",AddressingModes::ZeroPageX,2,6),
         OpCode::new(0x2E,"ROL",AddressingModes::Absolute,3,6),
         OpCode::new(0x3E,"ROL",AddressingModes::AbsoluteX,3,7),
         //ROR
-        OpCode::new(0x6A,"ROR",AddressingModes::NoAddressingMode,1,2),
+        OpCode::new(0x6A,"ROR",AddressingModes::Accumulator,1,2),
         OpCode::new(0x66,"ROR",AddressingModes::ZeroPage,2,5),
         OpCode::new(0x76,"ROR",AddressingModes::ZeroPageX,2,6),
         OpCode::new(0x6E,"ROR",AddressingModes::Absolute,3,6),
-        OpCode::new(0x7E,"ROT",AddressingModes::AbsoluteX,3,7),
+        OpCode::new(0x7E,"ROR",AddressingModes::AbsoluteX,3,7),
         //RTI
         OpCode::new(0x40,"RTI",AddressingModes::NoAddressingMode,1,6),
         //RTS
@@ -228,10 +243,10 @@ lazy_static! {
         OpCode::new(0x85,"STA",AddressingModes::ZeroPage,2,3),
         OpCode::new(0x95,"STA",AddressingModes::ZeroPageX,2,4),
         OpCode::new(0x8d,"STA",AddressingModes::Absolute,3,4),
-        OpCode::new(0x9d,"STA",AddressingModes::AbsoluteX,3,5),//+1 if page crossed
-        OpCode::new(0x99,"STA",AddressingModes::AbsoluteY,3,5),//+1 if page crossed
+        OpCode::new(0x9d,"STA",AddressingModes::AbsoluteX,3,5),
+        OpCode::new(0x99,"STA",AddressingModes::AbsoluteY,3,5),
         OpCode::new(0x81,"STA",AddressingModes::IndexedIndirect,2,6),
-        OpCode::new(0x91,"STA",AddressingModes::IndirectIndexed,2,6),//+1 if page crossed
+        OpCode::new(0x91,"STA",AddressingModes::IndirectIndexed,2,6),
         //STX
         OpCode::new(0x86,"STX",AddressingModes::ZeroPage,2,3),
         OpCode::new(0x96,"STX",AddressingModes::ZeroPageY,2,4),
@@ -252,16 +267,143 @@ lazy_static! {
         OpCode::new(0x9A,"TXS",AddressingModes::NoAddressingMode,1,2),
         //TYA
         OpCode::new(0x98 ,"TYA",AddressingModes::NoAddressingMode,1,2),
-
-        //BRK
-        OpCode::new(0x00,"BRK",AddressingModes::NoAddressingMode,1,7),
+        //非官方指令
+        //NOP
+        OpCode::unofficial(0x1A,"NOP",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x3A,"NOP",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x5A,"NOP",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x7A,"NOP",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0xDA,"NOP",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0xFA,"NOP",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x80,"NOP",AddressingModes::Immediate,2,2),
+        OpCode::unofficial(0x82,"NOP",AddressingModes::Immediate,2,2),
+        OpCode::unofficial(0x89,"NOP",AddressingModes::Immediate,2,2),
+        OpCode::unofficial(0xC2,"NOP",AddressingModes::Immediate,2,2),
+        OpCode::unofficial(0xE2,"NOP",AddressingModes::Immediate,2,2),
+        OpCode::unofficial(0x04,"NOP",AddressingModes::ZeroPage,2,3),
+        OpCode::unofficial(0x44,"NOP",AddressingModes::ZeroPage,2,3),
+        OpCode::unofficial(0x64,"NOP",AddressingModes::ZeroPage,2,3),
+        OpCode::unofficial(0x14,"NOP",AddressingModes::ZeroPageX,2,4),
+        OpCode::unofficial(0x34,"NOP",AddressingModes::ZeroPageX,2,4),
+        OpCode::unofficial(0x54,"NOP",AddressingModes::ZeroPageX,2,4),
+        OpCode::unofficial(0x74,"NOP",AddressingModes::ZeroPageX,2,4),
+        OpCode::unofficial(0xD4,"NOP",AddressingModes::ZeroPageX,2,4),
+        OpCode::unofficial(0xF4,"NOP",AddressingModes::ZeroPageX,2,4),
+        OpCode::unofficial(0x0C,"NOP",AddressingModes::Absolute,3,4),
+        OpCode::unofficial(0x1C,"NOP",AddressingModes::AbsoluteX,3,4),
+        OpCode::unofficial(0x3C,"NOP",AddressingModes::AbsoluteX,3,4),
+        OpCode::unofficial(0x5C,"NOP",AddressingModes::AbsoluteX,3,4),
+        OpCode::unofficial(0x7C,"NOP",AddressingModes::AbsoluteX,3,4),
+        OpCode::unofficial(0xDC,"NOP",AddressingModes::AbsoluteX,3,4),
+        OpCode::unofficial(0xFC,"NOP",AddressingModes::AbsoluteX,3,4),
+        //LAX
+        OpCode::unofficial(0xA7,"LAX",AddressingModes::ZeroPage,2,3),
+        OpCode::unofficial(0xB7,"LAX",AddressingModes::ZeroPageY,2,4),
+        OpCode::unofficial(0xAF,"LAX",AddressingModes::Absolute,3,4),
+        OpCode::unofficial(0xBF,"LAX",AddressingModes::AbsoluteY,3,4),
+        OpCode::unofficial(0xA3,"LAX",AddressingModes::IndexedIndirect,2,6),
+        OpCode::unofficial(0xB3,"LAX",AddressingModes::IndirectIndexed,2,5),
+        //SAX
+        OpCode::unofficial(0x87,"SAX",AddressingModes::ZeroPage,2,3),
+        OpCode::unofficial(0x97,"SAX",AddressingModes::ZeroPageY,2,4),
+        OpCode::unofficial(0x8F,"SAX",AddressingModes::Absolute,3,4),
+        OpCode::unofficial(0x83,"SAX",AddressingModes::IndexedIndirect,2,6),
+        //SBC
+        OpCode::unofficial(0xEB,"SBC",AddressingModes::Immediate,2,2),
+        //DCP
+        OpCode::unofficial(0xC7,"DCP",AddressingModes::ZeroPage,2,5),
+        OpCode::unofficial(0xD7,"DCP",AddressingModes::ZeroPageX,2,6),
+        OpCode::unofficial(0xCF,"DCP",AddressingModes::Absolute,3,6),
+        OpCode::unofficial(0xDF,"DCP",AddressingModes::AbsoluteX,3,7),
+        OpCode::unofficial(0xDB,"DCP",AddressingModes::AbsoluteY,3,7),
+        OpCode::unofficial(0xC3,"DCP",AddressingModes::IndexedIndirect,2,8),
+        OpCode::unofficial(0xD3,"DCP",AddressingModes::IndirectIndexed,2,8),
+        //ISB
+        OpCode::unofficial(0xE7,"ISB",AddressingModes::ZeroPage,2,5),
+        OpCode::unofficial(0xF7,"ISB",AddressingModes::ZeroPageX,2,6),
+        OpCode::unofficial(0xEF,"ISB",AddressingModes::Absolute,3,6),
+        OpCode::unofficial(0xFF,"ISB",AddressingModes::AbsoluteX,3,7),
+        OpCode::unofficial(0xFB,"ISB",AddressingModes::AbsoluteY,3,7),
+        OpCode::unofficial(0xE3,"ISB",AddressingModes::IndexedIndirect,2,8),
+        OpCode::unofficial(0xF3,"ISB",AddressingModes::IndirectIndexed,2,8),
+        //SLO
+        OpCode::unofficial(0x07,"SLO",AddressingModes::ZeroPage,2,5),
+        OpCode::unofficial(0x17,"SLO",AddressingModes::ZeroPageX,2,6),
+        OpCode::unofficial(0x0F,"SLO",AddressingModes::Absolute,3,6),
+        OpCode::unofficial(0x1F,"SLO",AddressingModes::AbsoluteX,3,7),
+        OpCode::unofficial(0x1B,"SLO",AddressingModes::AbsoluteY,3,7),
+        OpCode::unofficial(0x03,"SLO",AddressingModes::IndexedIndirect,2,8),
+        OpCode::unofficial(0x13,"SLO",AddressingModes::IndirectIndexed,2,8),
+        //RLA
+        OpCode::unofficial(0x27,"RLA",AddressingModes::ZeroPage,2,5),
+        OpCode::unofficial(0x37,"RLA",AddressingModes::ZeroPageX,2,6),
+        OpCode::unofficial(0x2F,"RLA",AddressingModes::Absolute,3,6),
+        OpCode::unofficial(0x3F,"RLA",AddressingModes::AbsoluteX,3,7),
+        OpCode::unofficial(0x3B,"RLA",AddressingModes::AbsoluteY,3,7),
+        OpCode::unofficial(0x23,"RLA",AddressingModes::IndexedIndirect,2,8),
+        OpCode::unofficial(0x33,"RLA",AddressingModes::IndirectIndexed,2,8),
+        //SRE
+        OpCode::unofficial(0x47,"SRE",AddressingModes::ZeroPage,2,5),
+        OpCode::unofficial(0x57,"SRE",AddressingModes::ZeroPageX,2,6),
+        OpCode::unofficial(0x4F,"SRE",AddressingModes::Absolute,3,6),
+        OpCode::unofficial(0x5F,"SRE",AddressingModes::AbsoluteX,3,7),
+        OpCode::unofficial(0x5B,"SRE",AddressingModes::AbsoluteY,3,7),
+        OpCode::unofficial(0x43,"SRE",AddressingModes::IndexedIndirect,2,8),
+        OpCode::unofficial(0x53,"SRE",AddressingModes::IndirectIndexed,2,8),
+        //RRA
+        OpCode::unofficial(0x67,"RRA",AddressingModes::ZeroPage,2,5),
+        OpCode::unofficial(0x77,"RRA",AddressingModes::ZeroPageX,2,6),
+        OpCode::unofficial(0x6F,"RRA",AddressingModes::Absolute,3,6),
+        OpCode::unofficial(0x7F,"RRA",AddressingModes::AbsoluteX,3,7),
+        OpCode::unofficial(0x7B,"RRA",AddressingModes::AbsoluteY,3,7),
+        OpCode::unofficial(0x63,"RRA",AddressingModes::IndexedIndirect,2,8),
+        OpCode::unofficial(0x73,"RRA",AddressingModes::IndirectIndexed,2,8),
+        //ANC
+        OpCode::unofficial(0x0B,"ANC",AddressingModes::Immediate,2,2),
+        OpCode::unofficial(0x2B,"ANC",AddressingModes::Immediate,2,2),
+        //ALR
+        OpCode::unofficial(0x4B,"ALR",AddressingModes::Immediate,2,2),
+        //ARR
+        OpCode::unofficial(0x6B,"ARR",AddressingModes::Immediate,2,2),
+        //AXS
+        OpCode::unofficial(0xCB,"AXS",AddressingModes::Immediate,2,2),
+        //不稳定的非官方指令，行为与具体芯片有关，这里取最常见的实现
+        //XAA
+        OpCode::unofficial(0x8B,"XAA",AddressingModes::Immediate,2,2),
+        //LXA
+        OpCode::unofficial(0xAB,"LXA",AddressingModes::Immediate,2,2),
+        //AHX
+        OpCode::unofficial(0x9F,"AHX",AddressingModes::AbsoluteY,3,5),
+        OpCode::unofficial(0x93,"AHX",AddressingModes::IndirectIndexed,2,6),
+        //SHY
+        OpCode::unofficial(0x9C,"SHY",AddressingModes::AbsoluteX,3,5),
+        //SHX
+        OpCode::unofficial(0x9E,"SHX",AddressingModes::AbsoluteY,3,5),
+        //TAS
+        OpCode::unofficial(0x9B,"TAS",AddressingModes::AbsoluteY,3,5),
+        //LAS
+        OpCode::unofficial(0xBB,"LAS",AddressingModes::AbsoluteY,3,4),
+        //KIL(JAM)，CPU停机
+        OpCode::unofficial(0x02,"KIL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x12,"KIL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x22,"KIL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x32,"KIL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x42,"KIL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x52,"KIL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x62,"KIL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x72,"KIL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0x92,"KIL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0xB2,"KIL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0xD2,"KIL",AddressingModes::NoAddressingMode,1,2),
+        OpCode::unofficial(0xF2,"KIL",AddressingModes::NoAddressingMode,1,2),
     ];
 
-   pub static ref  CPU_OPRAND_HASHMAP:HashMap<u8,&'static OpCode> ={
-            let mut m  = HashMap::new();
-             for opcode in &*CPU_OPRAND{
-               m.insert(opcode.opcode,opcode);
-             }
-             m
+   //按操作码字节索引，256个操作码均有定义
+   pub static ref CPU_OPRAND_TABLE:[Option<&'static OpCode>; 256] ={
+            let mut table = [None; 256];
+            for opcode in &*CPU_OPRAND{
+               table[opcode.opcode as usize] = Some(opcode);
+            }
+            table
     };
 }
@@ -0,0 +1,211 @@
+use crate::cpuoperand::AddressingModes;
+use crate::cpuoperand::OpCode;
+use crate::cpuoperand::CPU_OPRAND_TABLE;
+use crate::memory::Memory;
+use std::collections::HashMap;
+use std::fmt;
+
+//反汇编选项：labels把地址替换成标签，mark_unofficial在非官方指令前加*
+#[derive(Debug, Clone, Default)]
+pub struct DisassemblyOptions {
+    pub labels: HashMap<u16, String>,
+    pub mark_unofficial: bool,
+}
+
+//反汇编得到的一条指令
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassembledLine {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    pub unofficial: bool,
+}
+
+//8000  A9 10     LDA #$10
+impl fmt::Display for DisassembledLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "{:04X}  {:<9} {}",
+            self.address,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+//反汇编一段字节，第一个字节位于origin
+pub fn disassemble(
+    bytes: &[u8],
+    origin: u16,
+    options: &DisassemblyOptions,
+) -> Vec<DisassembledLine> {
+    let mut lines = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let line = disassemble_one(
+            &bytes[offset..],
+            origin.wrapping_add(offset as u16),
+            options,
+        );
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+    lines
+}
+
+//反汇编总线上start到end（含）之间的指令，只用peek读取，不会产生副作用
+pub fn disassemble_memory<M: Memory>(
+    memory: &M,
+    start: u16,
+    end: u16,
+    options: &DisassemblyOptions,
+) -> Vec<DisassembledLine> {
+    let bytes: Vec<u8> = (start..=end).map(|add| memory.peek(add)).collect();
+    disassemble(&bytes, start, options)
+}
+
+//反汇编一条指令，剩余字节不够一条指令时输出.byte
+fn disassemble_one(bytes: &[u8], address: u16, options: &DisassemblyOptions) -> DisassembledLine {
+    let opcode =
+        CPU_OPRAND_TABLE[bytes[0] as usize].filter(|opcode| opcode.bytes as usize <= bytes.len());
+    let Some(opcode) = opcode else {
+        return DisassembledLine {
+            address,
+            bytes: vec![bytes[0]],
+            text: format!(".byte ${:02X}", bytes[0]),
+            unofficial: false,
+        };
+    };
+
+    let bytes = bytes[..opcode.bytes as usize].to_vec();
+    let operand = format_operand(opcode, &bytes, address, options);
+    let mark = if opcode.unofficial && options.mark_unofficial {
+        "*"
+    } else {
+        ""
+    };
+    let text = if operand.is_empty() {
+        format!("{}{}", mark, opcode.opname)
+    } else {
+        format!("{}{} {}", mark, opcode.opname, operand)
+    };
+    DisassembledLine {
+        address,
+        bytes,
+        text,
+        unofficial: opcode.unofficial,
+    }
+}
+
+//有标签时用标签代替地址
+fn address_text(add: u16, width: usize, options: &DisassemblyOptions) -> String {
+    match options.labels.get(&add) {
+        Some(label) => label.clone(),
+        None => format!("${:0width$X}", add, width = width),
+    }
+}
+
+fn format_operand(
+    opcode: &OpCode,
+    bytes: &[u8],
+    address: u16,
+    options: &DisassemblyOptions,
+) -> String {
+    let para = bytes.get(1).copied().unwrap_or(0);
+    let para16 = u16::from_le_bytes([para, bytes.get(2).copied().unwrap_or(0)]);
+    let zero_page = |options| address_text(para as u16, 2, options);
+    let absolute = |options| address_text(para16, 4, options);
+    match opcode.addressmode {
+        AddressingModes::NoAddressingMode => String::new(),
+        AddressingModes::Accumulator => String::from("A"),
+        AddressingModes::Immediate => format!("#${:02X}", para),
+        AddressingModes::ZeroPage => zero_page(options),
+        AddressingModes::ZeroPageX => format!("{},X", zero_page(options)),
+        AddressingModes::ZeroPageY => format!("{},Y", zero_page(options)),
+        //分支目标相对于下一条指令
+        AddressingModes::Relative => {
+            let target = address.wrapping_add(2).wrapping_add(para as i8 as u16);
+            address_text(target, 4, options)
+        }
+        AddressingModes::Absolute => absolute(options),
+        AddressingModes::AbsoluteX => format!("{},X", absolute(options)),
+        AddressingModes::AbsoluteY => format!("{},Y", absolute(options)),
+        AddressingModes::Indirect => format!("({})", absolute(options)),
+        AddressingModes::IndexedIndirect => format!("({},X)", zero_page(options)),
+        AddressingModes::IndirectIndexed => format!("({}),Y", zero_page(options)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::FlatMemory;
+
+    fn texts(lines: &[DisassembledLine]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn disassemble_should_format_all_addressing_modes() {
+        let bytes = [
+            0x18, 0x0a, 0xa9, 0x10, 0xa5, 0x10, 0xb5, 0x10, 0xb6, 0x10, 0xad, 0x34, 0x12, 0xbd,
+            0x34, 0x12, 0xb9, 0x34, 0x12, 0x6c, 0x34, 0x12, 0xa1, 0x10, 0xb1, 0x10, 0xd0, 0xfe,
+        ];
+        let lines = disassemble(&bytes, 0x8000, &DisassemblyOptions::default());
+        assert_eq!(
+            texts(&lines),
+            vec![
+                "CLC",
+                "ASL A",
+                "LDA #$10",
+                "LDA $10",
+                "LDA $10,X",
+                "LDX $10,Y",
+                "LDA $1234",
+                "LDA $1234,X",
+                "LDA $1234,Y",
+                "JMP ($1234)",
+                "LDA ($10,X)",
+                "LDA ($10),Y",
+                "BNE $801A",
+            ]
+        );
+        assert_eq!(lines[2].to_string(), "8002  A9 10     LDA #$10");
+    }
+
+    #[test]
+    fn disassemble_should_substitute_labels() {
+        let mut options = DisassemblyOptions::default();
+        options.labels.insert(0x8000, String::from("loop"));
+        options.labels.insert(0x0010, String::from("counter"));
+        let lines = disassemble(&[0xc6, 0x10, 0xd0, 0xfc], 0x8000, &options);
+        assert_eq!(texts(&lines), vec!["DEC counter", "BNE loop"]);
+    }
+
+    #[test]
+    fn disassemble_should_mark_unofficial_opcodes() {
+        let mut options = DisassemblyOptions::default();
+        let lines = disassemble(&[0xa7, 0x10], 0x8000, &options);
+        assert!(lines[0].unofficial);
+        assert_eq!(lines[0].text, "LAX $10");
+        options.mark_unofficial = true;
+        let lines = disassemble(&[0xa7, 0x10], 0x8000, &options);
+        assert_eq!(lines[0].text, "*LAX $10");
+    }
+
+    #[test]
+    fn disassemble_should_emit_byte_for_truncated_instruction() {
+        let lines = disassemble(&[0xea, 0xad, 0x00], 0x8000, &DisassemblyOptions::default());
+        assert_eq!(texts(&lines), vec!["NOP", ".byte $AD", "BRK"]);
+    }
+
+    #[test]
+    fn disassemble_memory_should_read_bus_range() {
+        let mut memory = FlatMemory::new();
+        memory.load(0xc000, &[0x4c, 0x00, 0xc0]).unwrap();
+        let lines = disassemble_memory(&memory, 0xc000, 0xc002, &DisassemblyOptions::default());
+        assert_eq!(texts(&lines), vec!["JMP $C000"]);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::bus::Device;
+
+//标准手柄的8个按键，按读出的顺序排列
+pub const BUTTONA: u8 = 0b0000_0001;
+pub const BUTTONB: u8 = 0b0000_0010;
+pub const BUTTONSELECT: u8 = 0b0000_0100;
+pub const BUTTONSTART: u8 = 0b0000_1000;
+pub const BUTTONUP: u8 = 0b0001_0000;
+pub const BUTTONDOWN: u8 = 0b0010_0000;
+pub const BUTTONLEFT: u8 = 0b0100_0000;
+pub const BUTTONRIGHT: u8 = 0b1000_0000;
+const JOYPAD1: u16 = 0x4016;
+const JOYPAD2: u16 = 0x4017;
+//手柄只驱动低5位，高3位读到的是数据总线上残留的值
+const JOYPADOPENBUS: u8 = 0b1110_0000;
+
+//标准手柄：写$4016第0位锁存按键，之后每次读移出一位
+#[derive(Debug, Clone, Default)]
+pub struct Joypad {
+    strobe: bool,      //为1时一直重新锁存，读到的总是A键
+    button_index: u8,  //下一次读出第几个按键，8个读完后一直返回1
+    button_status: u8, //当前按下的按键
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_button(&mut self, button: u8, pressed: bool) {
+        if pressed {
+            self.button_status |= button;
+        } else {
+            self.button_status &= !button;
+        }
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.button_status
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let value = self.peek();
+        if !self.strobe && self.button_index < 8 {
+            self.button_index += 1;
+        }
+        value
+    }
+
+    //只返回第0位，高位由总线决定
+    pub fn peek(&self) -> u8 {
+        if self.button_index >= 8 {
+            return 1;
+        }
+        (self.button_status >> self.button_index) & 1
+    }
+}
+
+//两个手柄端口：$4016写入同时送到两个手柄，读$4016/$4017分别读1号和2号
+#[derive(Debug, Clone, Default)]
+pub struct Joypads {
+    joypads: [Joypad; 2],
+}
+
+impl Joypads {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn joypad_mut(&mut self, index: usize) -> &mut Joypad {
+        &mut self.joypads[index]
+    }
+}
+
+//$4017的写入是APU帧计数器，不处理
+impl Device for Joypads {
+    fn read(&mut self, add: u16, open_bus: u8) -> Option<u8> {
+        let bit = match add {
+            JOYPAD1 => self.joypads[0].read(),
+            JOYPAD2 => self.joypads[1].read(),
+            _ => return None,
+        };
+        Some(bit | (open_bus & JOYPADOPENBUS))
+    }
+
+    fn write(&mut self, add: u16, value: u8) -> bool {
+        if add != JOYPAD1 {
+            return false;
+        }
+        for joypad in &mut self.joypads {
+            joypad.write(value);
+        }
+        true
+    }
+
+    fn peek(&self, add: u16, open_bus: u8) -> Option<u8> {
+        let bit = match add {
+            JOYPAD1 => self.joypads[0].peek(),
+            JOYPAD2 => self.joypads[1].peek(),
+            _ => return None,
+        };
+        Some(bit | (open_bus & JOYPADOPENBUS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joypad_should_shift_out_buttons_in_order() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(BUTTONA, true);
+        joypad.set_button(BUTTONSTART, true);
+        joypad.set_button(BUTTONRIGHT, true);
+        joypad.write(1);
+        joypad.write(0);
+        let bits: Vec<u8> = (0..10).map(|_| joypad.read()).collect();
+        assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn joypad_should_return_button_a_while_strobe_is_high() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(BUTTONA, true);
+        joypad.write(1);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 1);
+        joypad.set_button(BUTTONA, false);
+        assert_eq!(joypad.read(), 0);
+    }
+}
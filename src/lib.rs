@@ -0,0 +1,12 @@
+pub mod apu;
+pub mod asm;
+pub mod bus;
+pub mod cartridges;
+pub mod cpu;
+pub mod cpuoperand;
+pub mod disasm;
+pub mod joypads;
+pub mod mapper;
+pub mod memory;
+pub mod nes;
+pub mod ppu;
@@ -1,8 +1,74 @@
+use nesemulator::cartridges::Rom;
+use nesemulator::disasm::{disassemble, DisassemblyOptions};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process;
 
-pub mod cpu;
-pub mod cpuoperand;
+const USAGE: &str = "usage: nesemulator disasm <file> [--origin <hex address>] [--labels <file>]";
+//PRG ROM映射到$8000，只有16K时从$C000开始也能看到同样的内容
+const PRGROMSTART: u16 = 0x8000;
 
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("disasm") => disasm_command(&args[1..]),
+        _ => Err(String::from(USAGE)),
+    };
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(2);
+    }
 }
 
+fn parse_address(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text.trim_start_matches('$'), 16)
+        .map_err(|_| format!("invalid address: {}", text))
+}
+
+//标签文件每行一个"地址 标签"，例如"C000 reset"，#开头的行是注释
+fn read_labels(path: &str) -> Result<HashMap<u16, String>, String> {
+    let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    let mut labels = HashMap::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(char::is_whitespace) {
+            Some((address, label)) => {
+                labels.insert(parse_address(address)?, label.trim().to_string());
+            }
+            None => return Err(format!("invalid label line: {}", line)),
+        }
+    }
+    Ok(labels)
+}
+
+//反汇编文件，iNES文件只反汇编PRG ROM，其他文件当作原始字节
+fn disasm_command(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut origin = None;
+    let mut options = DisassemblyOptions {
+        mark_unofficial: true,
+        ..Default::default()
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--origin" => origin = Some(parse_address(args.next().ok_or(USAGE)?)?),
+            "--labels" => options.labels = read_labels(args.next().ok_or(USAGE)?)?,
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(String::from(USAGE)),
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+    let raw = fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+    let bytes = match Rom::new(&raw) {
+        Ok(rom) => rom.prg_rom,
+        Err(_) => raw,
+    };
+    for line in disassemble(&bytes, origin.unwrap_or(PRGROMSTART), &options) {
+        println!("{}", line);
+    }
+    Ok(())
+}
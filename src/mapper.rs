@@ -0,0 +1,190 @@
+use crate::bus::Device;
+use crate::cartridges::{Mirroring, Rom, RomError};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const PRGRAMSTART: u16 = 0x6000;
+const PRGRAMSIZE: usize = 0x2000;
+const PRGROMSTART: u16 = 0x8000;
+const PRGROMBANKSIZE: usize = 0x4000;
+//没有CHR ROM的卡带带8K CHR RAM
+const CHRRAMSIZE: usize = 0x2000;
+
+//卡带：CPU从$4020开始的地址空间和PPU的$0000-$1FFF都由mapper决定
+pub trait Mapper {
+    //CPU读卡带空间，None表示卡带在这个地址不驱动数据总线
+    fn read_prg(&self, add: u16) -> Option<u8>;
+    fn write_prg(&mut self, add: u16, value: u8);
+    //PPU读写图案表
+    fn read_chr(&self, add: u16) -> u8;
+    fn write_chr(&mut self, add: u16, value: u8);
+    fn mirroring(&self) -> Mirroring;
+    //上电：清空卡带RAM，bank回到初始状态
+    fn power_on(&mut self) {
+        self.reset();
+    }
+    //复位线不接到卡带上，大多数mapper什么都不做
+    fn reset(&mut self) {}
+}
+
+//总线和PPU共用同一个卡带
+pub type SharedMapper = Rc<RefCell<dyn Mapper>>;
+
+//根据文件头的mapper编号创建卡带
+pub fn mapper_for(rom: Rom) -> Result<SharedMapper, RomError> {
+    match rom.mapper {
+        0 => Ok(Rc::new(RefCell::new(Nrom::new(
+            rom.prg_rom,
+            rom.chr_rom,
+            rom.screen_mirroring,
+        )))),
+        mapper => Err(RomError::UnsupportedMapper(mapper)),
+    }
+}
+
+//卡带作为总线设备，注册在$4020-$FFFF
+pub struct Cartridge {
+    mapper: SharedMapper,
+}
+
+impl Cartridge {
+    pub fn new(mapper: SharedMapper) -> Self {
+        Cartridge { mapper }
+    }
+}
+
+impl Device for Cartridge {
+    fn write(&mut self, add: u16, value: u8) -> bool {
+        self.mapper.borrow_mut().write_prg(add, value);
+        true
+    }
+
+    fn peek(&self, add: u16, _open_bus: u8) -> Option<u8> {
+        self.mapper.borrow().read_prg(add)
+    }
+
+    fn power_on(&mut self) {
+        self.mapper.borrow_mut().power_on();
+    }
+
+    fn reset(&mut self) {
+        self.mapper.borrow_mut().reset();
+    }
+}
+
+//mapper 0：16K或32K PRG ROM，8K CHR，没有bank切换
+//$6000-$7FFF是8K PRG RAM（Family Basic）
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        let chr_is_ram = chr_rom.is_empty();
+        Nrom {
+            prg_rom,
+            prg_ram: vec![0; PRGRAMSIZE],
+            chr: if chr_is_ram {
+                vec![0; CHRRAMSIZE]
+            } else {
+                chr_rom
+            },
+            chr_is_ram,
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    //只有一个16K bank时，$C000-$FFFF是$8000-$BFFF的镜像
+    fn read_prg(&self, add: u16) -> Option<u8> {
+        match add {
+            PRGRAMSTART..=0x7FFF => Some(self.prg_ram[(add - PRGRAMSTART) as usize]),
+            PRGROMSTART..=0xFFFF if !self.prg_rom.is_empty() => {
+                let mut offset = (add - PRGROMSTART) as usize;
+                if self.prg_rom.len() == PRGROMBANKSIZE {
+                    offset %= PRGROMBANKSIZE;
+                }
+                Some(self.prg_rom[offset % self.prg_rom.len()])
+            }
+            _ => None,
+        }
+    }
+
+    //ROM忽略写入
+    fn write_prg(&mut self, add: u16, value: u8) {
+        if let PRGRAMSTART..=0x7FFF = add {
+            self.prg_ram[(add - PRGRAMSTART) as usize] = value;
+        }
+    }
+
+    fn read_chr(&self, add: u16) -> u8 {
+        self.chr[add as usize % self.chr.len()]
+    }
+
+    fn write_chr(&mut self, add: u16, value: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[add as usize % len] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn power_on(&mut self) {
+        self.prg_ram.fill(0);
+        if self.chr_is_ram {
+            self.chr.fill(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nrom_should_mirror_single_prg_bank() {
+        let mut prg_rom = vec![0; PRGROMBANKSIZE];
+        prg_rom[0x3ffd] = 0xc0;
+        let nrom = Nrom::new(prg_rom, vec![], Mirroring::Horizontal);
+        assert_eq!(nrom.read_prg(0xbffd), Some(0xc0));
+        assert_eq!(nrom.read_prg(0xfffd), Some(0xc0));
+        assert_eq!(nrom.read_prg(0x5000), None);
+    }
+
+    #[test]
+    fn nrom_prg_ram_should_survive_reset_but_not_power_on() {
+        let mut nrom = Nrom::new(
+            vec![0; PRGROMBANKSIZE],
+            vec![0x11; CHRRAMSIZE],
+            Mirroring::Vertical,
+        );
+        nrom.write_prg(0x6000, 0x55);
+        nrom.write_prg(0x8000, 0x55);
+        nrom.write_chr(0x0000, 0x55);
+        assert_eq!(nrom.read_prg(0x8000), Some(0x00));
+        assert_eq!(nrom.read_chr(0x0000), 0x11);
+        nrom.reset();
+        assert_eq!(nrom.read_prg(0x6000), Some(0x55));
+        nrom.power_on();
+        assert_eq!(nrom.read_prg(0x6000), Some(0x00));
+    }
+
+    #[test]
+    fn mapper_for_should_reject_unknown_mapper() {
+        let rom = Rom {
+            prg_rom: vec![],
+            chr_rom: vec![],
+            mapper: 4,
+            screen_mirroring: Mirroring::Horizontal,
+        };
+        assert_eq!(mapper_for(rom).err(), Some(RomError::UnsupportedMapper(4)));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::cpu::CpuError;
+
+//CPU地址空间的大小，$0000-$FFFF
+pub const MEMORYSIZE: usize = 0x10000;
+
+//CPU通过这个接口访问总线
+pub trait Memory {
+    //CPU的总线读，可能有副作用（例如读PPU寄存器会清除标志）
+    fn read(&mut self, add: u16) -> u8;
+    fn write(&mut self, add: u16, value: u8);
+    //没有副作用的读，供调试器、跟踪器和测试使用
+    fn peek(&self, add: u16) -> u8;
+    //上电和复位时由CPU调用，让总线上的设备进入相应的状态
+    fn power_on(&mut self) {}
+    fn reset(&mut self) {}
+    //每个CPU周期在总线访问之后调用一次，让总线上的其他部件同步前进
+    fn tick(&mut self) {}
+    //写$4014请求的OAM DMA页号，CPU在当前指令结束后取走并执行
+    fn take_dma(&mut self) -> Option<u8> {
+        None
+    }
+    //总线上的NMI和IRQ输入线，CPU每个周期采样一次
+    fn nmi(&self) -> bool {
+        false
+    }
+    fn irq(&self) -> bool {
+        false
+    }
+    //PPU当前的扫描线和点，供跟踪器使用，没有PPU时返回None
+    fn ppu_position(&self) -> Option<(u16, u16)> {
+        None
+    }
+}
+
+//平坦的64K内存，没有任何映射，供单元测试和通用6502程序使用
+pub struct FlatMemory {
+    memory: Box<[u8; MEMORYSIZE]>,
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        FlatMemory {
+            memory: Box::new([0; MEMORYSIZE]),
+        }
+    }
+
+    //把内存映像复制到start开始的地方，例如从$0000开始的完整64K测试程序
+    //超出$FFFF时什么都不写，返回错误
+    pub fn load(&mut self, start: u16, image: &[u8]) -> Result<(), CpuError> {
+        let start = start as usize;
+        let max = MEMORYSIZE - start;
+        if image.len() > max {
+            return Err(CpuError::ProgramTooLarge {
+                size: image.len(),
+                max,
+            });
+        }
+        self.memory[start..start + image.len()].copy_from_slice(image);
+        Ok(())
+    }
+}
+
+impl Memory for FlatMemory {
+    fn read(&mut self, add: u16) -> u8 {
+        self.memory[add as usize]
+    }
+
+    fn write(&mut self, add: u16, value: u8) {
+        self.memory[add as usize] = value;
+    }
+
+    fn peek(&self, add: u16) -> u8 {
+        self.memory[add as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_memory_should_cover_whole_address_space() {
+        let mut memory = FlatMemory::new();
+        memory.write(0xffff, 0x55);
+        assert_eq!(memory.read(0xffff), 0x55);
+        assert_eq!(memory.peek(0xffff), 0x55);
+        assert_eq!(memory.peek(0x7fff), 0x00);
+    }
+
+    #[test]
+    fn flat_memory_should_load_image() {
+        let mut memory = FlatMemory::new();
+        memory.load(0x0400, &[0x01, 0x02]).unwrap();
+        assert_eq!(memory.peek(0x0400), 0x01);
+        assert_eq!(memory.peek(0x0401), 0x02);
+    }
+
+    #[test]
+    fn flat_memory_should_reject_image_past_end() {
+        let mut memory = FlatMemory::new();
+        assert_eq!(
+            memory.load(0x0400, &[0xea; MEMORYSIZE]),
+            Err(CpuError::ProgramTooLarge {
+                size: MEMORYSIZE,
+                max: MEMORYSIZE - 0x0400
+            })
+        );
+        assert_eq!(memory.peek(0x0400), 0x00);
+        assert!(memory.load(0xfffe, &[0x01, 0x02]).is_ok());
+        assert_eq!(memory.peek(0xffff), 0x02);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::bus::Bus;
+use crate::cartridges::{Rom, RomError};
+use crate::cpu::{CpuError, StepResult, CPU};
+use crate::memory::Memory;
+
+//主时钟分频：NTSC主时钟21.477272MHz，CPU 12分频、PPU 4分频，每个CPU周期3个点
+//PAL主时钟26.601712MHz，CPU 16分频、PPU 5分频，每个CPU周期3.2个点
+const NTSCCPUDIVIDER: u64 = 12;
+const NTSCPPUDIVIDER: u64 = 4;
+const PALCPUDIVIDER: u64 = 16;
+const PALPPUDIVIDER: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+}
+
+impl Region {
+    //一个CPU周期的主时钟周期数
+    pub fn cpu_divider(self) -> u64 {
+        match self {
+            Region::Ntsc => NTSCCPUDIVIDER,
+            Region::Pal => PALCPUDIVIDER,
+        }
+    }
+
+    //一个PPU点的主时钟周期数
+    pub fn ppu_divider(self) -> u64 {
+        match self {
+            Region::Ntsc => NTSCPPUDIVIDER,
+            Region::Pal => PALPPUDIVIDER,
+        }
+    }
+}
+
+//整台主机：CPU通过总线拥有PPU、APU、手柄和卡带
+//CPU每个总线周期都推动主时钟，并从总线采样PPU的NMI和设备的IRQ
+pub struct Nes {
+    cpu: CPU<Bus>,
+}
+
+impl Nes {
+    pub fn new(rom: Rom) -> Result<Self, RomError> {
+        Self::with_region(rom, Region::default())
+    }
+
+    pub fn with_region(rom: Rom, region: Region) -> Result<Self, RomError> {
+        let mut bus = Bus::from_rom(rom)?;
+        bus.set_region(region);
+        Ok(Self::with_bus(bus))
+    }
+
+    //使用已经配置好的总线，例如注册了额外设备的总线
+    pub fn with_bus(bus: Bus) -> Self {
+        let mut cpu = CPU::with_memory(bus);
+        cpu.power_on();
+        Nes { cpu }
+    }
+
+    pub fn cpu(&self) -> &CPU<Bus> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU<Bus> {
+        &mut self.cpu
+    }
+
+    pub fn bus(&self) -> &Bus {
+        self.cpu.memory()
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        self.cpu.memory_mut()
+    }
+
+    pub fn power_on(&mut self) {
+        self.cpu.power_on();
+    }
+
+    //复位按钮
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    //执行一条指令，PPU和APU在指令的每个周期里同步前进
+    pub fn step(&mut self) -> Result<StepResult, CpuError> {
+        self.cpu.step()
+    }
+
+    //至少执行n个CPU周期，返回实际执行的周期数
+    //以下几个函数和CPU的run系列一样，CPU停机时返回CpuError::Jammed
+    pub fn run_cycles(&mut self, n: u64) -> Result<u64, CpuError> {
+        self.cpu.run_for_cycles(n)
+    }
+
+    //执行到PPU进入下一条扫描线
+    pub fn run_scanline(&mut self) -> Result<(), CpuError> {
+        let scanline = self.bus().ppu().scanline();
+        self.cpu
+            .run_until(|cpu, _| cpu.memory().ppu().scanline() != scanline)
+            .map(|_| ())
+    }
+
+    //执行到PPU完成当前帧
+    pub fn run_frame(&mut self) -> Result<(), CpuError> {
+        let frame = self.bus().ppu().frame();
+        self.cpu
+            .run_until(|cpu, _| cpu.memory().ppu().frame() != frame)
+            .map(|_| ())
+    }
+
+    //没有副作用的读，供调试器使用
+    pub fn peek(&self, add: u16) -> u8 {
+        self.bus().peek(add)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridges::Mirroring;
+    use crate::cpu::Interrupt;
+
+    //PRG ROM从$8000开始，复位向量指向$8000，NMI和IRQ向量指向$9000
+    fn rom(program: &[u8], handler: &[u8]) -> Rom {
+        let mut prg_rom = vec![0xea; 0x8000];
+        prg_rom[..program.len()].copy_from_slice(program);
+        prg_rom[0x1000..0x1000 + handler.len()].copy_from_slice(handler);
+        prg_rom[0x7ffa..].copy_from_slice(&[0x00, 0x90, 0x00, 0x80, 0x00, 0x90]);
+        Rom {
+            prg_rom,
+            chr_rom: vec![],
+            mapper: 0,
+            screen_mirroring: Mirroring::Horizontal,
+        }
+    }
+
+    //JMP $8000
+    const LOOP: [u8; 3] = [0x4c, 0x00, 0x80];
+
+    #[test]
+    fn ppu_should_run_three_dots_per_cpu_cycle_on_ntsc() {
+        let mut nes = Nes::new(rom(&LOOP, &[])).unwrap();
+        //复位序列的7个周期同样推动PPU
+        assert_eq!(nes.bus().ppu().dot(), 7 * 3);
+        nes.run_cycles(1000).unwrap();
+        let cycles = nes.cpu().cycles();
+        let ppu = nes.bus().ppu();
+        assert_eq!(ppu.scanline() as u64 * 341 + ppu.dot() as u64, cycles * 3);
+        assert_eq!(nes.bus().master_clock(), cycles * 12);
+    }
+
+    #[test]
+    fn ppu_should_run_sixteen_dots_per_five_cpu_cycles_on_pal() {
+        let mut nes = Nes::with_region(rom(&LOOP, &[]), Region::Pal).unwrap();
+        nes.run_cycles(1000).unwrap();
+        let cycles = nes.cpu().cycles();
+        let ppu = nes.bus().ppu();
+        assert_eq!(
+            ppu.scanline() as u64 * 341 + ppu.dot() as u64,
+            cycles * 16 / 5
+        );
+    }
+
+    #[test]
+    fn run_frame_and_run_scanline_should_follow_ppu() {
+        let mut nes = Nes::new(rom(&LOOP, &[])).unwrap();
+        nes.run_scanline().unwrap();
+        assert_eq!(nes.bus().ppu().scanline(), 1);
+        nes.run_frame().unwrap();
+        assert_eq!(nes.bus().ppu().frame(), 1);
+        //指令粒度，最多越过一条指令
+        assert!(nes.bus().ppu().scanline() == 0 && nes.bus().ppu().dot() < 3 * 7);
+    }
+
+    #[test]
+    fn vblank_should_raise_nmi() {
+        //LDA #$80; STA $2000; loop: JMP loop
+        //NMI: INX; RTI
+        let program = [0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0x80];
+        let mut nes = Nes::new(rom(&program, &[0xe8, 0x40])).unwrap();
+        //上电时PPUSTATUS的VBlank已经置位，读一次清除
+        nes.bus_mut().read(0x2002);
+        nes.run_frame().unwrap();
+        nes.run_frame().unwrap();
+        assert_eq!(nes.cpu().registers().register_x, 2);
+    }
+
+    #[test]
+    fn cpu_step_should_receive_bus_interrupts() {
+        //不经过Nes::step，直接单步CPU也能响应PPU的NMI
+        let program = [0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0x80];
+        let mut nes = Nes::new(rom(&program, &[0xe8, 0x40])).unwrap();
+        nes.bus_mut().read(0x2002);
+        let nmi = nes
+            .cpu_mut()
+            .run_until(|_, result| result.interrupt.is_some())
+            .unwrap();
+        assert_eq!(nmi.interrupt, Some(Interrupt::Nmi));
+        //VBlank从第241条扫描线的第1个点开始，最晚在下一条JMP之后响应，
+        //再加上中断序列7个周期和处理程序的INX
+        let ppu = nes.bus().ppu();
+        assert_eq!(ppu.scanline(), 241);
+        assert!(ppu.dot() <= 3 + 3 * (3 + 7 + 2));
+    }
+
+    #[test]
+    fn run_functions_should_stop_on_jammed_cpu() {
+        //INX; KIL
+        let jammed = Some(CpuError::Jammed {
+            opcode: 0x02,
+            address: 0x8001,
+        });
+        let mut nes = Nes::new(rom(&[0xe8, 0x02], &[])).unwrap();
+        assert_eq!(nes.run_cycles(1000).err(), jammed);
+        let mut nes = Nes::new(rom(&[0xe8, 0x02], &[])).unwrap();
+        assert_eq!(nes.run_scanline().err(), jammed);
+        assert_eq!(nes.run_frame().err(), jammed);
+        assert_eq!(nes.cpu().registers().register_x, 1);
+    }
+
+    #[test]
+    fn apu_frame_irq_should_reach_cpu() {
+        //CLI; loop: JMP loop
+        //IRQ: INX; LDA $4015; RTI，读$4015清除帧中断
+        let program = [0x58, 0x4c, 0x01, 0x80];
+        let mut nes = Nes::new(rom(&program, &[0xe8, 0xad, 0x15, 0x40, 0x40])).unwrap();
+        nes.run_cycles(29820 - nes.cpu().cycles()).unwrap();
+        assert_eq!(nes.cpu().registers().register_x, 0);
+        nes.run_cycles(100).unwrap();
+        assert_eq!(nes.cpu().registers().register_x, 1);
+    }
+}
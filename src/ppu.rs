@@ -0,0 +1,536 @@
+use crate::bus::Device;
+use crate::cartridges::Mirroring;
+use crate::mapper::SharedMapper;
+use crate::nes::Region;
+
+const OAMSIZE: usize = 256;
+//PPU内部2K名称表RAM，四屏卡带另外提供2K，这里一起放在4K里
+const VRAMSIZE: usize = 0x1000;
+const PALETTESIZE: usize = 32;
+const NAMETABLESTART: u16 = 0x2000;
+const NAMETABLESIZE: usize = 0x0400;
+const PALETTESTART: u16 = 0x3F00;
+//PPU地址空间是14位的
+const PPUADDRESSMASK: u16 = 0x3FFF;
+
+//每条扫描线341个点，NTSC每帧262条扫描线，PAL每帧312条
+const DOTSPERSCANLINE: u16 = 341;
+const NTSCSCANLINES: u16 = 262;
+const PALSCANLINES: u16 = 312;
+//第241条扫描线的第1个点进入VBlank
+const VBLANKSCANLINE: u16 = 241;
+
+//PPUCTRL：第2位决定$2007之后地址加1还是加32，第7位在VBlank时产生NMI
+const CTRLINCREMENT32: u8 = 0b0000_0100;
+const CTRLNMIENABLE: u8 = 0b1000_0000;
+//PPUMASK：第3、4位显示背景和精灵
+const MASKRENDERING: u8 = 0b0001_1000;
+//PPUSTATUS只有高3位有意义：VBlank、精灵0命中、精灵溢出
+const STATUSVBLANK: u8 = 0b1000_0000;
+const STATUSFLAGS: u8 = 0b1110_0000;
+//上电后PPUSTATUS通常是+0+x xxxx：VBlank和精灵溢出置位
+const STATUSPOWERON: u8 = 0b1010_0000;
+//调色板每项只有6位，读$2007时高2位来自I/O锁存
+const PALETTEBITS: u8 = 0b0011_1111;
+//I/O锁存的每一位大约600毫秒没有刷新就衰减为0（NTSC每秒5369318个点）
+const IOLATCHDECAYDOTS: u64 = 3_221_591;
+
+//2C02的CPU接口：$2000-$2007八个寄存器
+pub struct Ppu {
+    mapper: SharedMapper,         //图案表和名称表镜像来自卡带
+    ctrl: u8,                     //$2000 PPUCTRL
+    mask: u8,                     //$2001 PPUMASK
+    status: u8,                   //$2002 PPUSTATUS
+    oam_addr: u8,                 //$2003 OAMADDR
+    oam: [u8; OAMSIZE],           //精灵属性表，通过$2004访问
+    vram_addr: u16,               //v：当前VRAM地址
+    temp_addr: u16,               //t：$2005/$2006写入的临时地址
+    fine_x: u8,                   //x：精细水平滚动
+    write_toggle: bool,           //w：$2005/$2006的第一次还是第二次写
+    read_buffer: u8,              //$2007读的延迟缓冲
+    vram: [u8; VRAMSIZE],         //名称表
+    palette: [u8; PALETTESIZE],   //调色板
+    io_latch: u8,                 //CPU和PPU之间数据线上残留的值，读只写寄存器时得到它
+    io_latch_refreshed: [u64; 8], //I/O锁存每一位最后一次被驱动为1的时间
+    clock: u64,                   //上电以来经过的点数
+    region: Region,               //决定每帧的扫描线数
+    scanline: u16,                //当前扫描线，最后一条是预渲染线
+    dot: u16,                     //当前扫描线上的点，0-340
+    frame: u64,                   //上电以来完成的帧数
+    odd_frame: bool,              //NTSC奇数帧在渲染时跳过预渲染线的最后一个点
+}
+
+impl Ppu {
+    pub fn new(mapper: SharedMapper) -> Self {
+        let mut ppu = Ppu {
+            mapper,
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; OAMSIZE],
+            vram_addr: 0,
+            temp_addr: 0,
+            fine_x: 0,
+            write_toggle: false,
+            read_buffer: 0,
+            vram: [0; VRAMSIZE],
+            palette: [0; PALETTESIZE],
+            io_latch: 0,
+            io_latch_refreshed: [0; 8],
+            clock: 0,
+            region: Region::default(),
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            odd_frame: false,
+        };
+        ppu.power_on();
+        ppu
+    }
+
+    //上电：所有寄存器清零，PPUSTATUS为上电值，从第0帧第0条扫描线的第0个点开始
+    pub fn power_on(&mut self) {
+        self.reset();
+        self.status = STATUSPOWERON;
+        self.oam_addr = 0;
+        self.vram_addr = 0;
+        self.oam = [0; OAMSIZE];
+        self.vram = [0; VRAMSIZE];
+        self.palette = [0; PALETTESIZE];
+        self.io_latch = 0;
+        self.io_latch_refreshed = [0; 8];
+        self.clock = 0;
+        self.scanline = 0;
+        self.dot = 0;
+        self.frame = 0;
+    }
+
+    //复位：PPUCTRL、PPUMASK、PPUSCROLL、写开关和读缓冲清零
+    //PPUSTATUS、OAMADDR、PPUADDR和显存保持不变
+    pub fn reset(&mut self) {
+        self.ctrl = 0;
+        self.mask = 0;
+        self.temp_addr = 0;
+        self.fine_x = 0;
+        self.write_toggle = false;
+        self.read_buffer = 0;
+        self.odd_frame = false;
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    pub fn ctrl(&self) -> u8 {
+        self.ctrl
+    }
+
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    //精灵属性表，OAM DMA和$2004写入这里
+    pub fn oam(&self) -> &[u8; OAMSIZE] {
+        &self.oam
+    }
+
+    //渲染使用的滚动位置：t寄存器和精细X
+    pub fn scroll(&self) -> (u16, u8) {
+        (self.temp_addr, self.fine_x)
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    //NMI输出线：VBlank期间且PPUCTRL允许NMI
+    pub fn nmi(&self) -> bool {
+        self.status & STATUSVBLANK != 0 && self.ctrl & CTRLNMIENABLE != 0
+    }
+
+    fn prerender_scanline(&self) -> u16 {
+        match self.region {
+            Region::Ntsc => NTSCSCANLINES - 1,
+            Region::Pal => PALSCANLINES - 1,
+        }
+    }
+
+    //每个PPU点调用一次
+    pub fn tick(&mut self) {
+        self.clock += 1;
+        self.dot += 1;
+        //NTSC奇数帧开启渲染时，预渲染线少一个点
+        let skip = self.region == Region::Ntsc
+            && self.odd_frame
+            && self.mask & MASKRENDERING != 0
+            && self.scanline == self.prerender_scanline()
+            && self.dot == DOTSPERSCANLINE - 1;
+        if self.dot == DOTSPERSCANLINE || skip {
+            self.dot = 0;
+            if self.scanline == self.prerender_scanline() {
+                self.scanline = 0;
+                self.frame += 1;
+                self.odd_frame = !self.odd_frame;
+            } else {
+                self.scanline += 1;
+            }
+        }
+        if self.dot == 1 {
+            if self.scanline == VBLANKSCANLINE {
+                self.status |= STATUSVBLANK;
+            } else if self.scanline == self.prerender_scanline() {
+                self.status &= !STATUSFLAGS;
+            }
+        }
+    }
+
+    //CPU读寄存器，add是$2000-$2007中的一个
+    pub fn read_register(&mut self, add: u16) -> u8 {
+        let (value, driven) = self.driven_bits(add);
+        let result = value | (self.io_latch() & !driven);
+        self.refresh_io_latch(value, driven);
+        match add & 0x0007 {
+            //读PPUSTATUS清除VBlank和写开关
+            2 => {
+                self.status &= !STATUSVBLANK;
+                self.write_toggle = false;
+            }
+            //调色板直接返回，缓冲里放的是调色板下面的名称表
+            7 => {
+                let add = self.vram_addr & PPUADDRESSMASK;
+                self.read_buffer = if add >= PALETTESTART {
+                    self.read_vram(add - 0x1000)
+                } else {
+                    self.read_vram(add)
+                };
+                self.increment_vram_addr();
+            }
+            _ => {}
+        }
+        result
+    }
+
+    //没有副作用的读，供调试器使用
+    pub fn peek_register(&self, add: u16) -> u8 {
+        let (value, driven) = self.driven_bits(add);
+        value | (self.io_latch() & !driven)
+    }
+
+    //寄存器读出的值和它驱动的位，只写寄存器不驱动任何位
+    fn driven_bits(&self, add: u16) -> (u8, u8) {
+        match add & 0x0007 {
+            2 => (self.status & STATUSFLAGS, STATUSFLAGS),
+            4 => (self.oam[self.oam_addr as usize], 0xFF),
+            7 => {
+                let add = self.vram_addr & PPUADDRESSMASK;
+                if add >= PALETTESTART {
+                    (self.read_vram(add) & PALETTEBITS, PALETTEBITS)
+                } else {
+                    (self.read_buffer, 0xFF)
+                }
+            }
+            _ => (0, 0),
+        }
+    }
+
+    //衰减之后的I/O锁存
+    fn io_latch(&self) -> u8 {
+        (0..8)
+            .filter(|bit| self.clock - self.io_latch_refreshed[*bit] < IOLATCHDECAYDOTS)
+            .fold(0, |value, bit| value | (self.io_latch & (1 << bit)))
+    }
+
+    //被驱动为1的位重新开始计时
+    fn refresh_io_latch(&mut self, value: u8, driven: u8) {
+        self.io_latch = (self.io_latch() & !driven) | (value & driven);
+        for bit in 0..8 {
+            if value & driven & (1 << bit) != 0 {
+                self.io_latch_refreshed[bit] = self.clock;
+            }
+        }
+    }
+
+    //写任何寄存器都会把整个字节放进I/O锁存
+    pub fn write_register(&mut self, add: u16, value: u8) {
+        self.refresh_io_latch(value, 0xFF);
+        match add & 0x0007 {
+            //名称表选择同时写入t的第10-11位
+            0 => {
+                self.ctrl = value;
+                self.temp_addr = (self.temp_addr & !0x0C00) | ((value as u16 & 0b11) << 10);
+            }
+            1 => self.mask = value,
+            3 => self.oam_addr = value,
+            4 => {
+                self.oam[self.oam_addr as usize] = value;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            //第一次写粗略X和精细X，第二次写粗略Y和精细Y
+            5 => {
+                if !self.write_toggle {
+                    self.fine_x = value & 0b111;
+                    self.temp_addr = (self.temp_addr & !0x001F) | (value as u16 >> 3);
+                } else {
+                    self.temp_addr = (self.temp_addr & !0x73E0)
+                        | ((value as u16 & 0b111) << 12)
+                        | ((value as u16 & 0b1111_1000) << 2);
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            //先写高6位，第二次写低8位并复制到v
+            6 => {
+                if !self.write_toggle {
+                    self.temp_addr = (self.temp_addr & 0x00FF) | ((value as u16 & 0x3F) << 8);
+                } else {
+                    self.temp_addr = (self.temp_addr & 0xFF00) | value as u16;
+                    self.vram_addr = self.temp_addr;
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            7 => {
+                self.write_vram(self.vram_addr & PPUADDRESSMASK, value);
+                self.increment_vram_addr();
+            }
+            _ => {}
+        }
+    }
+
+    fn increment_vram_addr(&mut self) {
+        let step = if self.ctrl & CTRLINCREMENT32 != 0 {
+            32
+        } else {
+            1
+        };
+        self.vram_addr = self.vram_addr.wrapping_add(step) & 0x7FFF;
+    }
+
+    //$2000-$2FFF按卡带的镜像方式映射到名称表RAM，$3000-$3EFF是它的镜像
+    fn nametable_index(&self, add: u16) -> usize {
+        let offset = (add - NAMETABLESTART) as usize % (4 * NAMETABLESIZE);
+        let table = offset / NAMETABLESIZE;
+        let table = match self.mapper.borrow().mirroring() {
+            Mirroring::Vertical => table % 2,
+            Mirroring::Horizontal => table / 2,
+            Mirroring::FourScreen => table,
+        };
+        table * NAMETABLESIZE + offset % NAMETABLESIZE
+    }
+
+    //$3F10/$3F14/$3F18/$3F1C是$3F00/$3F04/$3F08/$3F0C的镜像
+    fn palette_index(add: u16) -> usize {
+        let index = (add - PALETTESTART) as usize % PALETTESIZE;
+        if index >= 16 && index.is_multiple_of(4) {
+            index - 16
+        } else {
+            index
+        }
+    }
+
+    fn read_vram(&self, add: u16) -> u8 {
+        match add {
+            0x0000..=0x1FFF => self.mapper.borrow().read_chr(add),
+            NAMETABLESTART..=0x3EFF => self.vram[self.nametable_index(add)],
+            _ => self.palette[Self::palette_index(add)],
+        }
+    }
+
+    fn write_vram(&mut self, add: u16, value: u8) {
+        match add {
+            0x0000..=0x1FFF => self.mapper.borrow_mut().write_chr(add, value),
+            NAMETABLESTART..=0x3EFF => {
+                let index = self.nametable_index(add);
+                self.vram[index] = value;
+            }
+            _ => self.palette[Self::palette_index(add)] = value & PALETTEBITS,
+        }
+    }
+}
+
+//PPU自己维护I/O锁存，总是驱动数据总线
+//PPU按主时钟运行，由总线调用Ppu::tick，不使用CPU周期的tick
+impl Device for Ppu {
+    fn read(&mut self, add: u16, _open_bus: u8) -> Option<u8> {
+        Some(self.read_register(add))
+    }
+
+    fn write(&mut self, add: u16, value: u8) -> bool {
+        self.write_register(add, value);
+        true
+    }
+
+    fn peek(&self, add: u16, _open_bus: u8) -> Option<u8> {
+        Some(self.peek_register(add))
+    }
+
+    fn power_on(&mut self) {
+        Ppu::power_on(self);
+    }
+
+    fn reset(&mut self) {
+        Ppu::reset(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::Nrom;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn new_ppu(mirroring: Mirroring) -> Ppu {
+        Ppu::new(Rc::new(RefCell::new(Nrom::new(vec![], vec![], mirroring))))
+    }
+
+    fn set_vram_addr(ppu: &mut Ppu, add: u16) {
+        ppu.write_register(0x2006, (add >> 8) as u8);
+        ppu.write_register(0x2006, add as u8);
+    }
+
+    #[test]
+    fn ppudata_read_should_be_buffered() {
+        let mut ppu = new_ppu(Mirroring::Horizontal);
+        set_vram_addr(&mut ppu, 0x2000);
+        ppu.write_register(0x2007, 0x11);
+        ppu.write_register(0x2007, 0x22);
+        set_vram_addr(&mut ppu, 0x2000);
+        assert_eq!(ppu.read_register(0x2007), 0x00);
+        assert_eq!(ppu.read_register(0x2007), 0x11);
+        assert_eq!(ppu.read_register(0x2007), 0x22);
+    }
+
+    #[test]
+    fn nametables_should_follow_mirroring() {
+        let mut ppu = new_ppu(Mirroring::Vertical);
+        set_vram_addr(&mut ppu, 0x2005);
+        ppu.write_register(0x2007, 0x55);
+        assert_eq!(ppu.read_vram(0x2805), 0x55);
+        assert_eq!(ppu.read_vram(0x2405), 0x00);
+        assert_eq!(ppu.read_vram(0x3005), 0x55);
+        let mut ppu = new_ppu(Mirroring::Horizontal);
+        set_vram_addr(&mut ppu, 0x2005);
+        ppu.write_register(0x2007, 0x55);
+        assert_eq!(ppu.read_vram(0x2405), 0x55);
+    }
+
+    #[test]
+    fn palette_should_mirror_background_entries() {
+        let mut ppu = new_ppu(Mirroring::Horizontal);
+        set_vram_addr(&mut ppu, 0x3f10);
+        ppu.write_register(0x2007, 0x0f);
+        set_vram_addr(&mut ppu, 0x3f00);
+        assert_eq!(ppu.read_register(0x2007), 0x0f);
+    }
+
+    #[test]
+    fn ppustatus_read_should_clear_vblank_and_toggle() {
+        let mut ppu = new_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x2006, 0x21);
+        //低5位来自I/O锁存
+        assert_eq!(ppu.read_register(0x2002), STATUSPOWERON | 0b0000_0001);
+        assert_eq!(ppu.peek_register(0x2002) & STATUSVBLANK, 0);
+        set_vram_addr(&mut ppu, 0x2100);
+        assert_eq!(ppu.vram_addr, 0x2100);
+    }
+
+    #[test]
+    fn write_only_registers_should_read_io_latch() {
+        let mut ppu = new_ppu(Mirroring::Horizontal);
+        ppu.status = 0;
+        ppu.write_register(0x2001, 0b0001_1010);
+        assert_eq!(ppu.read_register(0x2000), 0b0001_1010);
+        assert_eq!(ppu.read_register(0x2002), 0b0001_1010);
+        set_vram_addr(&mut ppu, 0x3f00);
+        ppu.write_register(0x2007, 0xff);
+        set_vram_addr(&mut ppu, 0x3f00);
+        ppu.write_register(0x2005, 0b1100_0000);
+        assert_eq!(ppu.read_register(0x2007), 0xff);
+    }
+
+    #[test]
+    fn io_latch_should_decay() {
+        let mut ppu = new_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x2000, 0b1000_0001);
+        ppu.clock += IOLATCHDECAYDOTS / 2;
+        ppu.write_register(0x2003, 0b0000_0001);
+        assert_eq!(ppu.peek_register(0x2001), 0b0000_0001);
+        ppu.clock += IOLATCHDECAYDOTS / 2;
+        ppu.tick();
+        assert_eq!(ppu.peek_register(0x2001), 0b0000_0001);
+        ppu.clock += IOLATCHDECAYDOTS;
+        assert_eq!(ppu.peek_register(0x2001), 0);
+    }
+
+    fn run_to(ppu: &mut Ppu, scanline: u16, dot: u16) {
+        while ppu.scanline != scanline || ppu.dot != dot {
+            ppu.tick();
+        }
+    }
+
+    #[test]
+    fn vblank_should_start_at_scanline_241_and_end_at_prerender() {
+        let mut ppu = new_ppu(Mirroring::Horizontal);
+        ppu.status = 0;
+        ppu.write_register(0x2000, CTRLNMIENABLE);
+        run_to(&mut ppu, VBLANKSCANLINE, 0);
+        assert!(!ppu.nmi());
+        ppu.tick();
+        assert!(ppu.nmi());
+        run_to(&mut ppu, NTSCSCANLINES - 1, 1);
+        assert!(!ppu.nmi());
+        run_to(&mut ppu, 0, 0);
+        assert_eq!(ppu.frame(), 1);
+        assert_eq!(ppu.clock, DOTSPERSCANLINE as u64 * NTSCSCANLINES as u64);
+    }
+
+    #[test]
+    fn odd_frames_should_skip_a_dot_when_rendering() {
+        let mut ppu = new_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x2001, MASKRENDERING);
+        let frame = DOTSPERSCANLINE as u64 * NTSCSCANLINES as u64;
+        ppu.tick();
+        run_to(&mut ppu, 0, 0);
+        assert_eq!(ppu.clock, frame);
+        ppu.tick();
+        run_to(&mut ppu, 0, 0);
+        assert_eq!(ppu.clock, 2 * frame - 1);
+        let mut ppu = new_ppu(Mirroring::Horizontal);
+        ppu.set_region(Region::Pal);
+        ppu.write_register(0x2001, MASKRENDERING);
+        ppu.tick();
+        run_to(&mut ppu, 0, 0);
+        ppu.tick();
+        run_to(&mut ppu, 0, 0);
+        assert_eq!(ppu.clock, 2 * DOTSPERSCANLINE as u64 * PALSCANLINES as u64);
+    }
+
+    #[test]
+    fn reset_should_keep_status_and_vram_address() {
+        let mut ppu = new_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x2000, 0x80);
+        ppu.write_register(0x2001, 0x1e);
+        ppu.write_register(0x2003, 0x10);
+        set_vram_addr(&mut ppu, 0x2100);
+        ppu.write_register(0x2005, 0x10);
+        ppu.reset();
+        assert_eq!(ppu.ctrl(), 0);
+        assert_eq!(ppu.mask(), 0);
+        assert_eq!(ppu.scroll(), (0, 0));
+        assert!(!ppu.write_toggle);
+        assert_eq!(ppu.oam_addr, 0x10);
+        assert_eq!(ppu.vram_addr, 0x2100);
+        assert_eq!(ppu.peek_register(0x2002) & STATUSFLAGS, STATUSPOWERON);
+        ppu.power_on();
+        assert_eq!(ppu.oam_addr, 0);
+        assert_eq!(ppu.vram_addr, 0);
+    }
+}
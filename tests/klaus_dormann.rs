@@ -0,0 +1,72 @@
+use nesemulator::cpu::{StepEvent, Variant, CPU};
+use nesemulator::memory::FlatMemory;
+use std::fs;
+use std::path::Path;
+
+//Klaus Dormann的6502测试程序，放在tests/roms下，没有随仓库提供：
+//https://github.com/Klaus2m5/6502_65C02_functional_tests
+//6502_functional_test.bin在bin_files下，6502_decimal_test.bin需要用as65汇编
+//src/6502_decimal_test.a65得到，然后运行 cargo test --test klaus_dormann -- --ignored
+//6502_functional_test.bin是从$0000开始的64K映像，入口$0400，成功时停在$3469
+const FUNCTIONALPATH: &str = "tests/roms/6502_functional_test.bin";
+const FUNCTIONALSTART: u16 = 0x0400;
+const FUNCTIONALSUCCESS: u16 = 0x3469;
+//6502_decimal_test.bin从$0200开始，结束时停在自环上，$000B为0表示通过
+const DECIMALPATH: &str = "tests/roms/6502_decimal_test.bin";
+const DECIMALSTART: u16 = 0x0200;
+const DECIMALERROR: u16 = 0x000B;
+
+fn read_image(path: &str) -> Vec<u8> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    fs::read(root.join(path))
+        .unwrap_or_else(|error| panic!("{}: {}, see the top of this file", path, error))
+}
+
+//两个测试都会检查十进制运算，使用NMOS 6502型号
+fn nmos_cpu(memory: FlatMemory) -> CPU {
+    let mut cpu = CPU::with_variant(memory, Variant::Nmos6502);
+    cpu.power_on();
+    cpu
+}
+
+//从start开始执行，直到程序跳转到自身，返回自环所在的地址
+fn run_until_trapped(cpu: &mut CPU, start: u16) -> u16 {
+    cpu.set_program_counter(start);
+    let result = cpu
+        .run_until(|_, result| result.event == StepEvent::Trapped)
+        .unwrap();
+    result.address
+}
+
+#[test]
+#[ignore = "needs tests/roms/6502_functional_test.bin"]
+fn functional_test_should_reach_success_trap() {
+    let image = read_image(FUNCTIONALPATH);
+    let mut memory = FlatMemory::new();
+    memory.load(0x0000, &image).unwrap();
+    let mut cpu = nmos_cpu(memory);
+
+    let trap = run_until_trapped(&mut cpu, FUNCTIONALSTART);
+    assert_eq!(
+        trap, FUNCTIONALSUCCESS,
+        "trapped at {:04X}, see the listing for the failed test",
+        trap
+    );
+}
+
+#[test]
+#[ignore = "needs tests/roms/6502_decimal_test.bin"]
+fn decimal_test_should_pass() {
+    let image = read_image(DECIMALPATH);
+    let mut memory = FlatMemory::new();
+    memory.load(DECIMALSTART, &image).unwrap();
+    let mut cpu = nmos_cpu(memory);
+
+    let trap = run_until_trapped(&mut cpu, DECIMALSTART);
+    assert_eq!(
+        cpu.read_from_memory_u8(DECIMALERROR),
+        0,
+        "decimal test failed, trapped at {:04X}",
+        trap
+    );
+}
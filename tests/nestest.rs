@@ -0,0 +1,54 @@
+use nesemulator::bus::Bus;
+use nesemulator::cartridges::Rom;
+use nesemulator::cpu::CPU;
+use std::fs;
+use std::path::Path;
+
+//nestest.nes和参考日志nestest.log放在tests/roms下，没有随仓库提供，需要先下载：
+//  mkdir -p tests/roms && cd tests/roms
+//  curl -O http://www.qmtpro.com/~nes/misc/nestest.nes
+//  curl -O http://www.qmtpro.com/~nes/misc/nestest.log
+//然后运行 cargo test --test nestest -- --ignored
+const ROMPATH: &str = "tests/roms/nestest.nes";
+const LOGPATH: &str = "tests/roms/nestest.log";
+//自动模式的入口，不需要PPU
+const AUTOMATIONSTART: u16 = 0xC000;
+//出错时显示前面几行日志
+const CONTEXTLINES: usize = 5;
+
+#[test]
+#[ignore = "needs tests/roms/nestest.nes and tests/roms/nestest.log"]
+fn nestest_should_match_golden_log() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let raw = fs::read(root.join(ROMPATH))
+        .unwrap_or_else(|error| panic!("{}: {}, see the top of this file", ROMPATH, error));
+    let log = fs::read_to_string(root.join(LOGPATH))
+        .unwrap_or_else(|error| panic!("{}: {}, see the top of this file", LOGPATH, error));
+
+    let rom = Rom::new(&raw).unwrap();
+    let mut cpu = CPU::with_memory(Bus::from_rom(rom).unwrap());
+    cpu.power_on();
+    cpu.set_program_counter(AUTOMATIONSTART);
+
+    let expected: Vec<&str> = log.lines().map(str::trim_end).collect();
+    for (index, line) in expected.iter().enumerate() {
+        let actual = cpu.trace();
+        if actual != *line {
+            let context = expected[index.saturating_sub(CONTEXTLINES)..index].join("\n");
+            panic!(
+                "nestest diverged at line {}\n{}\nexpected: {}\nactual:   {}",
+                index + 1,
+                context,
+                line,
+                actual
+            );
+        }
+        if let Err(error) = cpu.step() {
+            panic!("nestest stopped at line {}: {}\n{}", index + 1, error, line);
+        }
+    }
+
+    //$02和$03保存官方指令和非官方指令测试的错误码
+    assert_eq!(cpu.read_from_memory_u8(0x0002), 0x00);
+    assert_eq!(cpu.read_from_memory_u8(0x0003), 0x00);
+}
@@ -0,0 +1,169 @@
+use nesemulator::cpu::{Registers, CPU};
+use nesemulator::cpuoperand::CPU_OPRAND_TABLE;
+use nesemulator::memory::{FlatMemory, Memory};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+//SingleStepTests/ProcessorTests的nes6502测试，每个操作码一个文件（00.json - ff.json）
+//默认目录为tests/singlestep，可以用SINGLESTEP_DIR指定，数据没有随仓库提供：
+//https://github.com/SingleStepTests/ProcessorTests 的nes6502/v1目录
+//然后运行 cargo test --test single_step -- --ignored
+const DEFAULTDIR: &str = "tests/singlestep";
+//KIL让CPU停机，停机后的总线活动没有模拟
+const SKIPPED: &[&str] = &["KIL"];
+
+//记录每一次总线访问：(地址, 值, 是否写)
+struct RecordingMemory {
+    memory: FlatMemory,
+    accesses: Vec<(u16, u8, bool)>,
+}
+
+impl Memory for RecordingMemory {
+    fn read(&mut self, add: u16) -> u8 {
+        let value = self.memory.read(add);
+        self.accesses.push((add, value, false));
+        value
+    }
+
+    fn write(&mut self, add: u16, value: u8) {
+        self.accesses.push((add, value, true));
+        self.memory.write(add, value);
+    }
+
+    fn peek(&self, add: u16) -> u8 {
+        self.memory.peek(add)
+    }
+}
+
+fn number(value: &Value) -> u64 {
+    value.as_u64().expect("expected a number")
+}
+
+fn registers(state: &Value) -> Registers {
+    Registers {
+        register_a: number(&state["a"]) as u8,
+        register_x: number(&state["x"]) as u8,
+        register_y: number(&state["y"]) as u8,
+        status: number(&state["p"]) as u8,
+        stack_pointer: number(&state["s"]) as u8,
+        program_counter: number(&state["pc"]) as u16,
+    }
+}
+
+//ram是[地址, 值]的列表
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    state["ram"]
+        .as_array()
+        .expect("expected ram")
+        .iter()
+        .map(|entry| (number(&entry[0]) as u16, number(&entry[1]) as u8))
+        .collect()
+}
+
+//cycles是[地址, 值, "read"/"write"]的列表
+fn bus_log(case: &Value) -> Vec<(u16, u8, bool)> {
+    case["cycles"]
+        .as_array()
+        .expect("expected cycles")
+        .iter()
+        .map(|cycle| {
+            (
+                number(&cycle[0]) as u16,
+                number(&cycle[1]) as u8,
+                cycle[2] == "write",
+            )
+        })
+        .collect()
+}
+
+//执行一个用例，返回第一处不一致
+fn run_case(case: &Value) -> Result<(), String> {
+    let mut cpu = CPU::with_memory(RecordingMemory {
+        memory: FlatMemory::new(),
+        accesses: vec![],
+    });
+    for (add, value) in ram(&case["initial"]) {
+        cpu.write_to_memory_u8(add, value);
+    }
+    cpu.set_registers(registers(&case["initial"]));
+    cpu.memory_mut().accesses.clear();
+
+    cpu.step().map_err(|error| error.to_string())?;
+
+    let expected = registers(&case["final"]);
+    if cpu.registers() != expected {
+        return Err(format!(
+            "registers: expected {:?}, got {:?}",
+            expected,
+            cpu.registers()
+        ));
+    }
+    for (add, value) in ram(&case["final"]) {
+        let actual = cpu.read_from_memory_u8(add);
+        if actual != value {
+            return Err(format!(
+                "ram {:04X}: expected {:02X}, got {:02X}",
+                add, value, actual
+            ));
+        }
+    }
+    let expected = bus_log(case);
+    if cpu.memory().accesses != expected {
+        return Err(format!(
+            "bus: expected {:?}, got {:?}",
+            expected,
+            cpu.memory().accesses
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs tests/singlestep"]
+fn single_step_tests_should_pass() {
+    let dir = env::var("SINGLESTEP_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(DEFAULTDIR));
+    assert!(
+        dir.is_dir(),
+        "{} not found, see the top of this file",
+        dir.display()
+    );
+
+    let mut failures = vec![];
+    for code in 0..=0xffu8 {
+        let path = dir.join(format!("{:02x}.json", code));
+        let Ok(text) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let opname = CPU_OPRAND_TABLE[code as usize].map_or("???", |opcode| opcode.opname);
+        if SKIPPED.contains(&opname) {
+            println!("{:02X} {}: skipped", code, opname);
+            continue;
+        }
+
+        let cases: Vec<Value> = serde_json::from_str(&text).expect("invalid test file");
+        let mut first_failure = None;
+        let mut passed = 0;
+        for case in &cases {
+            match run_case(case) {
+                Ok(()) => passed += 1,
+                Err(message) => {
+                    first_failure.get_or_insert_with(|| format!("{}: {}", case["name"], message));
+                }
+            }
+        }
+        println!("{:02X} {}: {}/{} passed", code, opname, passed, cases.len());
+        if let Some(message) = first_failure {
+            failures.push(format!("{:02X} {}: {}", code, opname, message));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} opcodes failed\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}